@@ -1,7 +1,9 @@
 ﻿use crate::digits_network::get_digits;
 use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::float::Float;
 use crate::nural::nural_network::{NuralNetwork, NuralNetworkLossKind};
 use crate::nural::dense_layer::DenseLayer;
+use crate::nural::gradient_clipping::GradientClipping;
 use crate::utils::shuffle_iter::ShuffleIterExt;
 
 pub fn bin_digit_network() {
@@ -35,7 +37,8 @@ fn learn(bin_digits: &[Vec<[i32; 28]>; 10]) {
         ],
         0.1,
         NuralNetworkLossKind::Mse,
-    );
+    )
+    .with_gradient_clipping(GradientClipping::new().with_global_norm(5.0));
 
     let bin_digit_train_data = bin_digits
         .iter()
@@ -50,8 +53,8 @@ fn learn(bin_digits: &[Vec<[i32; 28]>; 10]) {
                 .map(|d| {
                     (
                         d.iter()
-                            .map(|&d| d as f64)
-                            .collect::<Vec<f64>>()
+                            .map(|&d| d as Float)
+                            .collect::<Vec<Float>>()
                             .as_slice()
                             .to_owned(),
                         output.as_slice().to_owned(),
@@ -99,7 +102,7 @@ fn test_concrete() {
         0b0000000000000000000000000000,
     ];
 
-    let bin_digit_data = bin_digit.iter().map(|&d| d as f64).collect::<Vec<f64>>();
+    let bin_digit_data = bin_digit.iter().map(|&d| d as Float).collect::<Vec<Float>>();
     let output = nural_network.predict(bin_digit_data.as_slice());
 
     let predicted_digit = output
@@ -120,8 +123,8 @@ fn test_random(bin_digits: &[Vec<[i32; 28]>; 10]) {
         let digit_variant = rand::random_range(501..1000);
         let bin_digit_data = bin_digits[digit][digit_variant]
             .iter()
-            .map(|&d| d as f64)
-            .collect::<Vec<f64>>();
+            .map(|&d| d as Float)
+            .collect::<Vec<Float>>();
 
         let output = nural_network.predict(bin_digit_data.as_slice());
 
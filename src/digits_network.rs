@@ -1,56 +1,30 @@
 ﻿use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::float::Float;
 use crate::nural::nural_network::{NuralNetwork, NuralNetworkLossKind};
 use crate::nural::dense_layer::DenseLayer;
-use crate::utils::shuffle_iter::ShuffleIterExt;
+use crate::nural::nural_network_training::{CheckpointPolicy, NuralNetworkTraining};
+use crate::nural::quantized_network::QuantizedNetwork;
 use std::fs::File;
 use std::io::Read;
+use std::ops::Range;
 
 pub const DIGIT_COUNT: usize = 1000;
 pub const DIGIT_SIZE: usize = 28;
 pub const DIGIT_BUFFER_SIZE: usize = DIGIT_SIZE.pow(2);
 
-pub fn digit_network() {
-    let digits = [
-        get_digits("./data/data0.bin"),
-        get_digits("./data/data1.bin"),
-        get_digits("./data/data2.bin"),
-        get_digits("./data/data3.bin"),
-        get_digits("./data/data4.bin"),
-        get_digits("./data/data5.bin"),
-        get_digits("./data/data6.bin"),
-        get_digits("./data/data7.bin"),
-        get_digits("./data/data8.bin"),
-        get_digits("./data/data9.bin"),
-    ];
-
-    learn(&digits);
+// With `resume` training continues from the newest checkpoint of an interrupted run,
+// otherwise the checkpoints of earlier runs are removed and training starts over.
+pub fn digit_network(resume: bool) {
+    let digits = get_all_digits();
 
-    let nural_network = NuralNetwork::load_file("./data/digits.tnn").unwrap();
-
-    for digit in 0..=9 {
-        let digit_variant = rand::random_range(501..1000);
-        let digit_data = digits[digit][digit_variant]
-            .iter()
-            .map(|&d| d as f64 / 255.0)
-            .collect::<Vec<f64>>();
+    learn(&digits, resume);
 
-        let output = nural_network.predict(digit_data.as_slice());
-
-        let predicted_digit = output
-            .iter()
-            .enumerate()
-            .max_by_key(|(_, d)| (**d * 100.0) as i32)
-            .unwrap()
-            .0;
-        println!(
-            "Actual: {} [{}] Prediction: {}",
-            digit, digit_variant, predicted_digit
-        );
-    }
+    let nural_network = NuralNetwork::load_file("./data/digits.tnn").unwrap();
+    print_predictions(&nural_network, &digits);
 }
 
-fn learn(digits: &[Vec<Vec<u8>>; 10]) {
-    let mut nural_network = NuralNetwork::new(
+fn learn(digits: &[Vec<Vec<u8>>; 10], resume: bool) {
+    let nural_network = NuralNetwork::new(
         vec![
             Box::new(DenseLayer::new(28 * 28, 128)),
             Box::new(ActivationLayer::new(ActivationLayerKind::ReLu)),
@@ -64,33 +38,105 @@ fn learn(digits: &[Vec<Vec<u8>>; 10]) {
         NuralNetworkLossKind::Mse,
     );
 
-    let data = digits
+    let data = digit_samples(digits, 0..100);
+
+    let checkpoint_policy = CheckpointPolicy::new("./data/digits_checkpoints")
+        .with_every(10)
+        .with_keep_last(2)
+        .with_keep_best(1);
+    let training = NuralNetworkTraining::new(nural_network, 0);
+    let mut training = match checkpoint_policy.latest() {
+        Some(checkpoint) if resume => training.resume_from(&checkpoint).unwrap(),
+        _ => {
+            checkpoint_policy.clear().unwrap();
+            training
+        }
+    };
+
+    training.train(data.as_slice(), 100, &checkpoint_policy).unwrap();
+    training.network().save_file("./data/digits.tnn").unwrap();
+}
+
+// Quantizes a trained digits model to int8 next to it, calibrated on the training samples and
+// compared on the held out ones.
+pub fn quantize(model_path: &str) {
+    let digits = get_all_digits();
+    let calibration_inputs = digit_samples(&digits, 0..100)
+        .into_iter()
+        .map(|(input, _)| input)
+        .collect::<Vec<_>>();
+    let test_data = digit_samples(&digits, 501..1000);
+
+    let float_network = NuralNetwork::load_file(model_path).unwrap();
+    let quantized_network = QuantizedNetwork::quantize(
+        NuralNetwork::load_file(model_path).unwrap(),
+        &calibration_inputs,
+    );
+    let report = quantized_network.compare(&float_network, &test_data);
+    println!(
+        "Float accuracy: {:.3} Int8 accuracy: {:.3} Agreement: {:.3} Max difference: {:.4}",
+        report.float_accuracy, report.quantized_accuracy, report.agreement, report.max_difference
+    );
+    let quantized_path = format!("{}_int8.tnn", model_path.trim_end_matches(".tnn"));
+    quantized_network.save_file(&quantized_path).unwrap();
+}
+
+// Summary and weight/activation statistics of a trained digits model over a sample of every
+// digit.
+pub fn inspect(model_path: &str) {
+    let nural_network = NuralNetwork::load_file(model_path).unwrap();
+    let data = digit_samples(&get_all_digits(), 0..20);
+
+    println!("{}\n", nural_network.summary(&[DIGIT_BUFFER_SIZE]).unwrap());
+    println!("{}", nural_network.statistics(&data));
+}
+
+// Loads the images of all ten digits, indexed by digit.
+pub fn get_all_digits() -> [Vec<Vec<u8>>; 10] {
+    std::array::from_fn(|digit| get_digits(&format!("./data/data{}.bin", digit)))
+}
+
+// One-hot labelled samples of every digit, the range selects the variants of each digit.
+pub fn digit_samples(
+    digits: &[Vec<Vec<u8>>; 10],
+    range: Range<usize>,
+) -> Vec<(Vec<Float>, Vec<Float>)> {
+    digits
         .iter()
         .enumerate()
         .flat_map(|(digit, digit_data)| {
-            let mut output = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+            let mut output = vec![0.0; 10];
             output[digit] = 1.0;
-
-            digit_data
+            digit_data[range.clone()]
                 .iter()
-                .take(100)
-                .map(|d| {
-                    (
-                        d.iter()
-                            .map(|&d| d as f64 / 255.0)
-                            .collect::<Vec<f64>>()
-                            .as_slice()
-                            .to_owned(),
-                        output.as_slice().to_owned(),
-                    )
-                })
-                .collect::<Vec<_>>()
+                .map(|d| (d.iter().map(|&d| d as Float / 255.0).collect(), output.clone()))
+                .collect::<Vec<(Vec<Float>, Vec<Float>)>>()
         })
-        .shuffle()
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+// Predicts a random held out variant of every digit.
+pub fn print_predictions(nural_network: &NuralNetwork, digits: &[Vec<Vec<u8>>; 10]) {
+    for (digit, digit_data) in digits.iter().enumerate() {
+        let digit_variant = rand::random_range(501..1000);
+        let digit_data = digit_data[digit_variant]
+            .iter()
+            .map(|&d| d as Float / 255.0)
+            .collect::<Vec<Float>>();
 
-    nural_network.train(data.as_slice(), 100);
-    nural_network.save_file("./data/digits.tnn").unwrap();
+        let output = nural_network.predict(digit_data.as_slice());
+
+        let predicted_digit = output
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, d)| (**d * 100.0) as i32)
+            .unwrap()
+            .0;
+        println!(
+            "Actual: {} [{}] Prediction: {}",
+            digit, digit_variant, predicted_digit
+        );
+    }
 }
 
 pub fn get_digits(path: &str) -> Vec<Vec<u8>> {
@@ -3,14 +3,30 @@
 extern crate core;
 extern crate openblas_src;
 
-use crate::digits_network::digit_network;
+use crate::digits_network::{digit_network, inspect, quantize};
+use crate::rnn_digits_network::rnn_digit_network;
+use crate::transformer_digits_network::transformer_digit_network;
 
 mod bin_digits_network;
 pub mod digits_network;
 mod nural;
+mod rnn_digits_network;
+mod transformer_digits_network;
 mod utils;
 mod xor_network;
 
+// `digit inspect [model.tnn]` prints the summary and statistics of a trained model,
+// `digit quantize [model.tnn]` writes an int8 copy of it next to the model and `digit resume`
+// continues an interrupted training run. `digit rnn` and `digit transformer` train the
+// sequence models. Without arguments the digits network is trained.
 fn main() {
-    digit_network()
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.get(1).map(String::as_str) {
+        Some("inspect") => inspect(args.get(2).map_or("./data/digits.tnn", String::as_str)),
+        Some("quantize") => quantize(args.get(2).map_or("./data/digits.tnn", String::as_str)),
+        Some("resume") => digit_network(true),
+        Some("rnn") => rnn_digit_network(),
+        Some("transformer") => transformer_digit_network(),
+        _ => digit_network(false),
+    }
 }
\ No newline at end of file
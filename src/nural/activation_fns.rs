@@ -1,8 +1,10 @@
-﻿use std::f64::consts::E;
+﻿use crate::nural::float::Float;
+
+const E: Float = std::f64::consts::E as Float;
 
 pub struct ActivationFn<'a> {
-    pub dx: &'a dyn Fn(f64) -> f64,
-    pub fx: &'a dyn Fn(f64) -> f64,
+    pub dx: &'a dyn Fn(Float) -> Float,
+    pub fx: &'a dyn Fn(Float) -> Float,
 }
 
 pub const RELU: ActivationFn = ActivationFn {
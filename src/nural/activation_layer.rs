@@ -1,5 +1,7 @@
 ﻿use crate::nural::activation_fns::*;
+use crate::nural::float::Float;
 use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
 use serde::{Deserialize, Serialize};
 use std::any::Any;
 
@@ -20,6 +22,10 @@ impl ActivationLayer {
         ActivationLayer { kind }
     }
 
+    pub fn kind(&self) -> &ActivationLayerKind {
+        &self.kind
+    }
+
     fn activation_fn(&self) -> ActivationFn {
         match self.kind {
             ActivationLayerKind::ReLu => RELU,
@@ -34,23 +40,31 @@ impl NuralNetworkLayer for ActivationLayer {
         self
     }
 
-    fn backward(
-        &mut self,
-        input: &[f64],
-        _output: &[f64],
-        output_gradient: &[f64],
-        _learning_rate: f64,
-    ) -> Vec<f64> {
-        input.iter()
-            .zip(output_gradient.iter())
-            .map(|(input_val, output_gradient_val)| (self.activation_fn().dx)(*input_val) * *output_gradient_val)
-            .collect()
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 
-    fn forward(&self, input: &[f64]) -> Vec<f64> {
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
         input
             .iter()
             .map(|val| (self.activation_fn().fx)(*val))
-            .collect::<Vec<f64>>()
+            .collect::<Vec<Float>>()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let input_gradient = input.iter()
+            .zip(output_gradient.iter())
+            .map(|(input_val, output_gradient_val)| (self.activation_fn().dx)(*input_val) * *output_gradient_val)
+            .collect();
+        (input_gradient, ParameterGradient::default())
     }
 }
@@ -0,0 +1,32 @@
+﻿use crate::nural::float::Float;
+use ndarray::{Array, Dimension, IxDyn};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Deserialize, Serialize)]
+struct ArrayData {
+    data: Vec<Float>,
+    shape: Vec<usize>,
+}
+
+pub fn serialize<S, D>(array: &Array<Float, D>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    D: Dimension,
+{
+    let data = ArrayData {
+        data: array.iter().copied().collect(),
+        shape: array.shape().to_vec(),
+    };
+    data.serialize(serializer)
+}
+
+pub fn deserialize<'a, De, D>(deserializer: De) -> Result<Array<Float, D>, De::Error>
+where
+    De: Deserializer<'a>,
+    D: Dimension,
+{
+    let data = ArrayData::deserialize(deserializer)?;
+    Array::from_shape_vec(IxDyn(&data.shape), data.data)
+        .and_then(|array| array.into_dimensionality::<D>())
+        .map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,249 @@
+use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{s, Array1, Array2, ArrayViewD, ArrayViewMutD, Axis};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Multi-head scaled dot-product self-attention over a `[steps, features]` sequence, the
+// features are split evenly between the heads.
+#[derive(Deserialize, Serialize)]
+pub struct AttentionLayer {
+    heads: usize,
+    #[serde(with = "array_serde")]
+    key_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    output_bias: Array1<Float>,
+    #[serde(with = "array_serde")]
+    output_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    query_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    value_weights: Array2<Float>,
+}
+
+struct AttentionState {
+    attention: Vec<Array2<Float>>,
+    context: Array2<Float>,
+    input: Array2<Float>,
+    keys: Array2<Float>,
+    output: Array2<Float>,
+    queries: Array2<Float>,
+    values: Array2<Float>,
+}
+
+impl AttentionLayer {
+    pub fn new(features: usize, heads: usize) -> AttentionLayer {
+        assert_eq!(
+            features % heads,
+            0,
+            "Features must be divisible by the number of heads"
+        );
+
+        AttentionLayer {
+            heads,
+            key_weights: init_weights(features),
+            output_bias: Array1::zeros(features),
+            output_weights: init_weights(features),
+            query_weights: init_weights(features),
+            value_weights: init_weights(features),
+        }
+    }
+
+    fn features(&self) -> usize {
+        self.output_bias.len()
+    }
+
+    fn forward_state(&self, input: &[Float]) -> AttentionState {
+        let features = self.features();
+        let head_features = features / self.heads;
+        let scale = 1.0 / (head_features as Float).sqrt();
+
+        let input = Array2::from_shape_vec((input.len() / features, features), input.to_vec())
+            .expect("AttentionLayer input must be a [steps, features] sequence");
+        let queries = input.dot(&self.query_weights.t());
+        let keys = input.dot(&self.key_weights.t());
+        let values = input.dot(&self.value_weights.t());
+
+        let mut attention = Vec::with_capacity(self.heads);
+        let mut context = Array2::zeros(input.raw_dim());
+        for head in 0..self.heads {
+            let head_cols = s![.., head * head_features..(head + 1) * head_features];
+            let scores = queries.slice(head_cols).dot(&keys.slice(head_cols).t()) * scale;
+            let head_attention = softmax_rows(scores);
+
+            context
+                .slice_mut(head_cols)
+                .assign(&head_attention.dot(&values.slice(head_cols)));
+            attention.push(head_attention);
+        }
+
+        let output = context.dot(&self.output_weights.t()) + &self.output_bias;
+        AttentionState {
+            attention,
+            context,
+            input,
+            keys,
+            output,
+            queries,
+            values,
+        }
+    }
+}
+
+impl NuralNetworkLayer for AttentionLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // The four projections and the output bias of every step plus the attention scores and the weighted sum of
+    // the values, which grow with the square of the sequence length.
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let steps = input_shape.first().copied().unwrap_or(1);
+        let projection_weights = self.key_weights.len()
+            + self.output_weights.len()
+            + self.query_weights.len()
+            + self.value_weights.len();
+        steps * (2 * projection_weights + self.output_bias.len())
+            + 4 * steps * steps * self.features()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        self.forward_state(input).output.iter().copied().collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let features = self.features();
+        let head_features = features / self.heads;
+        let scale = 1.0 / (head_features as Float).sqrt();
+        let state = self.forward_state(input);
+
+        let output_gradient =
+            Array2::from_shape_vec(state.input.raw_dim(), output_gradient.to_vec()).unwrap();
+        let output_weights_gradient = output_gradient.t().dot(&state.context);
+        let output_bias_gradient = output_gradient.sum_axis(Axis(0));
+        let context_gradient = output_gradient.dot(&self.output_weights);
+
+        let mut queries_gradient = Array2::<Float>::zeros(state.input.raw_dim());
+        let mut keys_gradient = Array2::<Float>::zeros(state.input.raw_dim());
+        let mut values_gradient = Array2::<Float>::zeros(state.input.raw_dim());
+        for (head, head_attention) in state.attention.iter().enumerate() {
+            let head_cols = s![.., head * head_features..(head + 1) * head_features];
+            let head_context_gradient = context_gradient.slice(head_cols);
+
+            let attention_gradient = head_context_gradient.dot(&state.values.slice(head_cols).t());
+            values_gradient
+                .slice_mut(head_cols)
+                .assign(&head_attention.t().dot(&head_context_gradient));
+
+            // Softmax backward, row by row.
+            let row_dot = (&attention_gradient * head_attention)
+                .sum_axis(Axis(1))
+                .insert_axis(Axis(1));
+            let scores_gradient = head_attention * &(&attention_gradient - &row_dot) * scale;
+
+            queries_gradient
+                .slice_mut(head_cols)
+                .assign(&scores_gradient.dot(&state.keys.slice(head_cols)));
+            keys_gradient
+                .slice_mut(head_cols)
+                .assign(&scores_gradient.t().dot(&state.queries.slice(head_cols)));
+        }
+
+        let input_gradient = queries_gradient.dot(&self.query_weights)
+            + keys_gradient.dot(&self.key_weights)
+            + values_gradient.dot(&self.value_weights);
+
+        let parameter_gradient = keys_gradient
+            .t()
+            .dot(&state.input)
+            .iter()
+            .chain(output_bias_gradient.iter())
+            .chain(output_weights_gradient.iter())
+            .chain(queries_gradient.t().dot(&state.input).iter())
+            .chain(values_gradient.t().dot(&state.input).iter())
+            .copied()
+            .collect();
+        (input_gradient.iter().copied().collect(), parameter_gradient)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape {
+            [_, features] if *features == self.features() => Ok(input_shape.to_vec()),
+            _ => Err(format!(
+                "AttentionLayer expects input shape [steps, {}], got {:?}",
+                self.features(),
+                input_shape
+            )),
+        }
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![
+            self.key_weights.view().into_dyn(),
+            self.output_bias.view().into_dyn(),
+            self.output_weights.view().into_dyn(),
+            self.query_weights.view().into_dyn(),
+            self.value_weights.view().into_dyn(),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.key_weights.view_mut().into_dyn(),
+            self.output_bias.view_mut().into_dyn(),
+            self.output_weights.view_mut().into_dyn(),
+            self.query_weights.view_mut().into_dyn(),
+            self.value_weights.view_mut().into_dyn(),
+        ]
+    }
+}
+
+fn softmax_rows(scores: Array2<Float>) -> Array2<Float> {
+    let mut probabilities = scores;
+    for mut row in probabilities.rows_mut() {
+        let max = row.fold(Float::NEG_INFINITY, |max, &val| max.max(val));
+        row.mapv_inplace(|val| (val - max).exp());
+        let sum = row.sum();
+        row.mapv_inplace(|val| val / sum);
+    }
+    probabilities
+}
+
+fn init_weights(features: usize) -> Array2<Float> {
+    let mut rng = rand::rng();
+    let limit = 1.0 / (features as Float).sqrt();
+    Array2::from_shape_fn((features, features), |_| rng.random_range(-limit..limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = AttentionLayer::new(8, 2);
+        let input = (0..3 * 8)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        let check = gradient_check(&mut layer, &input);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+}
@@ -0,0 +1,197 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{ArrayViewD, ArrayViewMutD};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Runs every branch on the same input and concatenates the branch outputs, an empty
+// branch passes the input through unchanged.
+#[derive(Deserialize, Serialize)]
+pub struct ConcatLayer {
+    branches: Vec<Vec<Box<dyn NuralNetworkLayer>>>,
+}
+
+impl ConcatLayer {
+    pub fn new(branches: Vec<Vec<Box<dyn NuralNetworkLayer>>>) -> ConcatLayer {
+        ConcatLayer { branches }
+    }
+}
+
+impl NuralNetworkLayer for ConcatLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        self.branches
+            .iter()
+            .map(|branch| {
+                let mut shape = input_shape.to_vec();
+                let mut flops = 0;
+                for layer in branch.iter() {
+                    flops += layer.flops(&shape);
+                    shape = layer.output_shape(&shape).unwrap_or_default();
+                }
+                flops
+            })
+            .sum()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        self.branches
+            .iter()
+            .flat_map(|branch| forward_branch(branch, input).pop().unwrap())
+            .collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let mut input_gradient = vec![0.0; input.len()];
+        let mut parameter_gradient = Vec::<Float>::new();
+        let mut offset = 0;
+
+        for branch in self.branches.iter() {
+            let outputs = forward_branch(branch, input);
+            let size = outputs.last().unwrap().len();
+
+            let mut gradient = output_gradient[offset..offset + size].to_vec();
+            let mut layer_parameter_gradients = vec![vec![]; branch.len()];
+            for (layer_index, layer) in branch.iter().enumerate().rev() {
+                let (layer_input_gradient, layer_parameter_gradient) =
+                    layer.gradient(&outputs[layer_index], &outputs[layer_index + 1], &gradient);
+                gradient = layer_input_gradient;
+                layer_parameter_gradients[layer_index] = layer_parameter_gradient
+                    .to_dense(layer.parameter_len())
+                    .into_owned();
+            }
+            parameter_gradient.extend(layer_parameter_gradients.into_iter().flatten());
+
+            input_gradient
+                .iter_mut()
+                .zip(gradient.iter())
+                .for_each(|(input_gradient_val, gradient_val)| *input_gradient_val += gradient_val);
+            offset += size;
+        }
+
+        (input_gradient, parameter_gradient.into())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        let mut size = 0;
+        for (branch_index, branch) in self.branches.iter().enumerate() {
+            let shape = branch
+                .iter()
+                .try_fold(input_shape.to_vec(), |shape, layer| {
+                    layer.output_shape(&shape)
+                })
+                .map_err(|err| format!("ConcatLayer branch {}: {}", branch_index, err))?;
+            size += shape.iter().product::<usize>();
+        }
+        Ok(vec![size])
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        self.branches
+            .iter()
+            .flat_map(|branch| branch.iter())
+            .flat_map(|layer| layer.parameters())
+            .collect()
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        self.branches
+            .iter_mut()
+            .flat_map(|branch| branch.iter_mut())
+            .flat_map(|layer| layer.parameters_mut())
+            .collect()
+    }
+
+    fn penalty(&self) -> Float {
+        self.branches
+            .iter()
+            .flat_map(|branch| branch.iter())
+            .map(|layer| layer.penalty())
+            .sum()
+    }
+
+    // Hands every layer its own part of the gradient so layers with their own update rule
+    // keep it.
+    fn update(&mut self, parameter_gradient: &ParameterGradient, learning_rate: Float) {
+        let parameter_gradient = parameter_gradient.to_dense(self.parameter_len());
+        let mut offset = 0;
+        for layer in self
+            .branches
+            .iter_mut()
+            .flat_map(|branch| branch.iter_mut())
+        {
+            let parameter_len = layer.parameter_len();
+            layer.update(
+                &parameter_gradient[offset..offset + parameter_len]
+                    .iter()
+                    .copied()
+                    .collect(),
+                learning_rate,
+            );
+            offset += parameter_len;
+        }
+    }
+}
+
+fn forward_branch(branch: &[Box<dyn NuralNetworkLayer>], input: &[Float]) -> Vec<Vec<Float>> {
+    let mut outputs = vec![input.to_vec(); 1];
+    for layer in branch.iter() {
+        let output = layer.forward(outputs.last().unwrap());
+        outputs.push(output);
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::dense_layer::DenseLayer;
+    use crate::nural::gradient_check::gradient_check;
+
+    fn layer() -> ConcatLayer {
+        ConcatLayer::new(vec![
+            vec![
+                Box::new(DenseLayer::new(4, 3)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+            ],
+            vec![],
+            vec![Box::new(DenseLayer::new(4, 2))],
+        ])
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = layer();
+        let input = (0..4)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        let check = gradient_check(&mut layer, &input);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+
+    #[test]
+    fn output_shape_sums_the_branches() {
+        let layer = layer();
+        assert_eq!(layer.output_shape(&[4]), Ok(vec![3 + 4 + 2]));
+        assert!(layer.output_shape(&[5]).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array1, Array3, Array4, ArrayView3, ArrayViewD, ArrayViewMutD};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Images are flat `[channels, height, width]` buffers. Filters only see the channels of
+// their group, with as many groups as channels every channel gets its own (depthwise)
+// filter. A dilation above 1 spreads the kernel taps apart to widen the receptive field.
+#[derive(Deserialize, Serialize)]
+pub struct Conv2dLayer {
+    #[serde(with = "array_serde")]
+    bias: Array1<Float>,
+    dilation: usize,
+    groups: usize,
+    input_shape: [usize; 3],
+    padding: usize,
+    stride: usize,
+    #[serde(with = "array_serde")]
+    weights: Array4<Float>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Conv2dGeometry {
+    pub dilation: usize,
+    pub groups: usize,
+    pub input_shape: [usize; 3],
+    pub kernel_shape: [usize; 4],
+    pub padding: usize,
+    pub stride: usize,
+}
+
+impl Conv2dLayer {
+    pub fn new(input_shape: [usize; 3], filters: usize, kernel_size: usize) -> Conv2dLayer {
+        Conv2dLayer::grouped(input_shape, filters, kernel_size, 1)
+    }
+
+    pub fn depthwise(input_shape: [usize; 3], kernel_size: usize) -> Conv2dLayer {
+        Conv2dLayer::grouped(input_shape, input_shape[0], kernel_size, input_shape[0])
+    }
+
+    pub fn grouped(
+        input_shape: [usize; 3],
+        filters: usize,
+        kernel_size: usize,
+        groups: usize,
+    ) -> Conv2dLayer {
+        assert!(
+            groups > 0 && input_shape[0].is_multiple_of(groups) && filters.is_multiple_of(groups),
+            "Channels and filters must be divisible by the number of groups"
+        );
+
+        let group_channels = input_shape[0] / groups;
+        let mut rng = rand::rng();
+        let limit = 1.0 / ((group_channels * kernel_size * kernel_size) as Float).sqrt();
+        Conv2dLayer {
+            bias: Array1::zeros(filters),
+            dilation: 1,
+            groups,
+            input_shape,
+            padding: 0,
+            stride: 1,
+            weights: Array4::from_shape_fn(
+                (filters, group_channels, kernel_size, kernel_size),
+                |_| rng.random_range(-limit..limit),
+            ),
+        }
+    }
+
+    pub fn pointwise(input_shape: [usize; 3], filters: usize) -> Conv2dLayer {
+        Conv2dLayer::new(input_shape, filters, 1)
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> Conv2dLayer {
+        assert!(dilation > 0, "Dilation must be at least 1");
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> Conv2dLayer {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Conv2dLayer {
+        assert!(stride > 0, "Stride must be at least 1");
+        self.stride = stride;
+        self
+    }
+
+    // Everything but the parameters, enough to walk the taps of the convolution.
+    pub fn geometry(&self) -> Conv2dGeometry {
+        Conv2dGeometry {
+            dilation: self.dilation,
+            groups: self.groups,
+            input_shape: self.input_shape,
+            kernel_shape: self.weights.dim().into(),
+            padding: self.padding,
+            stride: self.stride,
+        }
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        self.geometry().output_size()
+    }
+
+    fn for_each_tap(&self, visit: impl FnMut([usize; 3], [usize; 3], [usize; 4])) {
+        self.geometry().for_each_tap(visit)
+    }
+}
+
+impl Conv2dGeometry {
+    pub fn output_size(&self) -> (usize, usize) {
+        self.checked_output_size()
+            .expect("Conv2dLayer kernel must fit into the padded input")
+    }
+
+    // None when the dilated kernel is larger than the padded input.
+    pub fn checked_output_size(&self) -> Option<(usize, usize)> {
+        let [_, height, width] = self.input_shape;
+        let span = self.dilation * (self.kernel_shape[2] - 1) + 1;
+        Some((
+            (height + 2 * self.padding).checked_sub(span)? / self.stride + 1,
+            (width + 2 * self.padding).checked_sub(span)? / self.stride + 1,
+        ))
+    }
+
+    // Calls `visit(input_index, output_index, kernel_index)` for every output pixel, kernel
+    // tap and input pixel that are connected, padding taps are skipped.
+    pub fn for_each_tap(&self, mut visit: impl FnMut([usize; 3], [usize; 3], [usize; 4])) {
+        let [_, height, width] = self.input_shape;
+        let [filters, group_channels, kernel_size, _] = self.kernel_shape;
+        let group_filters = filters / self.groups;
+        let (output_height, output_width) = self.output_size();
+
+        for filter in 0..filters {
+            let first_channel = filter / group_filters * group_channels;
+            for group_channel in 0..group_channels {
+                for output_row in 0..output_height {
+                    for output_col in 0..output_width {
+                        for kernel_row in 0..kernel_size {
+                            let row = (output_row * self.stride + kernel_row * self.dilation)
+                                .wrapping_sub(self.padding);
+                            if row >= height {
+                                continue;
+                            }
+
+                            for kernel_col in 0..kernel_size {
+                                let col = (output_col * self.stride + kernel_col * self.dilation)
+                                    .wrapping_sub(self.padding);
+                                if col >= width {
+                                    continue;
+                                }
+
+                                visit(
+                                    [first_channel + group_channel, row, col],
+                                    [filter, output_row, output_col],
+                                    [filter, group_channel, kernel_row, kernel_col],
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl NuralNetworkLayer for Conv2dLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        let (output_height, output_width) = self.output_size();
+        let (filters, group_channels, kernel_size, _) = self.weights.dim();
+        filters
+            * output_height
+            * output_width
+            * (2 * group_channels * kernel_size * kernel_size + 1)
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let (output_height, output_width) = self.output_size();
+        let input = ArrayView3::from_shape(self.input_shape, input)
+            .expect("Conv2dLayer input size mismatch");
+
+        let mut output = Array3::<Float>::zeros((self.bias.len(), output_height, output_width));
+        for (filter, mut filter_output) in output.outer_iter_mut().enumerate() {
+            filter_output.fill(self.bias[filter]);
+        }
+        self.for_each_tap(|input_index, output_index, kernel_index| {
+            output[output_index] += input[input_index] * self.weights[kernel_index];
+        });
+
+        output.into_iter().collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let (output_height, output_width) = self.output_size();
+        let input = ArrayView3::from_shape(self.input_shape, input).unwrap();
+        let output_gradient = ArrayView3::from_shape(
+            (self.bias.len(), output_height, output_width),
+            output_gradient,
+        )
+        .unwrap();
+
+        let mut input_gradient = Array3::<Float>::zeros(input.raw_dim());
+        let mut weights_gradient = Array4::<Float>::zeros(self.weights.raw_dim());
+        self.for_each_tap(|input_index, output_index, kernel_index| {
+            input_gradient[input_index] +=
+                output_gradient[output_index] * self.weights[kernel_index];
+            weights_gradient[kernel_index] += output_gradient[output_index] * input[input_index];
+        });
+
+        let bias_gradient = output_gradient
+            .outer_iter()
+            .map(|filter_gradient| filter_gradient.sum())
+            .collect::<Array1<Float>>();
+
+        let parameter_gradient = bias_gradient
+            .iter()
+            .chain(weights_gradient.iter())
+            .copied()
+            .collect();
+        (input_gradient.into_iter().collect(), parameter_gradient)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        if input_shape != self.input_shape {
+            return Err(format!(
+                "Conv2dLayer expects input shape {:?}, got {:?}",
+                self.input_shape, input_shape
+            ));
+        }
+
+        match self.geometry().checked_output_size() {
+            Some((output_height, output_width)) => {
+                Ok(vec![self.bias.len(), output_height, output_width])
+            }
+            None => Err(format!(
+                "Conv2dLayer kernel {:?} does not fit into input shape {:?} with padding {}",
+                self.weights.shape(),
+                self.input_shape,
+                self.padding
+            )),
+        }
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![self.bias.view().into_dyn(), self.weights.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.weights.view_mut().into_dyn(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    fn image(input_shape: [usize; 3]) -> Vec<Float> {
+        (0..input_shape.iter().product::<usize>())
+            .map(|index| (index as Float * 0.37).sin())
+            .collect()
+    }
+
+    fn assert_gradients_match(mut layer: Conv2dLayer, input_shape: [usize; 3]) {
+        let check = gradient_check(&mut layer, &image(input_shape));
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+
+    #[test]
+    fn grouped_gradients_match_finite_differences() {
+        assert_gradients_match(Conv2dLayer::grouped([4, 5, 5], 6, 3, 2), [4, 5, 5]);
+    }
+
+    #[test]
+    fn depthwise_gradients_match_finite_differences() {
+        assert_gradients_match(Conv2dLayer::depthwise([3, 5, 5], 3), [3, 5, 5]);
+    }
+
+    #[test]
+    fn pointwise_gradients_match_finite_differences() {
+        assert_gradients_match(Conv2dLayer::pointwise([3, 4, 4], 2), [3, 4, 4]);
+    }
+
+    #[test]
+    fn dilated_gradients_match_finite_differences() {
+        assert_gradients_match(
+            Conv2dLayer::new([2, 7, 7], 2, 3).with_dilation(2),
+            [2, 7, 7],
+        );
+    }
+
+    #[test]
+    fn strided_and_padded_gradients_match_finite_differences() {
+        assert_gradients_match(
+            Conv2dLayer::new([2, 6, 6], 3, 3)
+                .with_stride(2)
+                .with_padding(1),
+            [2, 6, 6],
+        );
+    }
+
+    #[test]
+    fn output_shape_follows_the_geometry() {
+        let layer = Conv2dLayer::new([2, 6, 6], 3, 3)
+            .with_stride(2)
+            .with_padding(1);
+        assert_eq!(layer.output_shape(&[2, 6, 6]), Ok(vec![3, 3, 3]));
+        let layer = Conv2dLayer::new([2, 7, 7], 2, 3).with_dilation(2);
+        assert_eq!(layer.output_shape(&[2, 7, 7]), Ok(vec![2, 3, 3]));
+        assert!(Conv2dLayer::new([1, 2, 2], 1, 3)
+            .output_shape(&[1, 2, 2])
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Stride must be at least 1")]
+    fn zero_stride_is_rejected() {
+        Conv2dLayer::new([1, 4, 4], 1, 3).with_stride(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dilation must be at least 1")]
+    fn zero_dilation_is_rejected() {
+        Conv2dLayer::new([1, 4, 4], 1, 3).with_dilation(0);
+    }
+}
@@ -0,0 +1,243 @@
+﻿use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array1, Array3, Array4, ArrayView3, ArrayViewD, ArrayViewMutD};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Images are flat `[channels, height, width]` buffers. Every input pixel scatters its
+// kernel into the output, so a stride of 2 doubles the image size.
+#[derive(Deserialize, Serialize)]
+pub struct ConvTranspose2dLayer {
+    #[serde(with = "array_serde")]
+    bias: Array1<Float>,
+    input_shape: [usize; 3],
+    padding: usize,
+    stride: usize,
+    #[serde(with = "array_serde")]
+    weights: Array4<Float>,
+}
+
+impl ConvTranspose2dLayer {
+    pub fn new(
+        input_shape: [usize; 3],
+        filters: usize,
+        kernel_size: usize,
+        stride: usize,
+    ) -> ConvTranspose2dLayer {
+        assert!(stride > 0, "Stride must be at least 1");
+
+        let mut rng = rand::rng();
+        let limit = 1.0 / ((input_shape[0] * kernel_size * kernel_size) as Float).sqrt();
+        ConvTranspose2dLayer {
+            bias: Array1::zeros(filters),
+            input_shape,
+            padding: 0,
+            stride,
+            weights: Array4::from_shape_fn(
+                (input_shape[0], filters, kernel_size, kernel_size),
+                |_| rng.random_range(-limit..limit),
+            ),
+        }
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> ConvTranspose2dLayer {
+        self.padding = padding;
+        self
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        self.checked_output_size()
+            .expect("ConvTranspose2dLayer padding must be smaller than the output")
+    }
+
+    // None when the padding crops away more than the whole output.
+    fn checked_output_size(&self) -> Option<(usize, usize)> {
+        let [_, height, width] = self.input_shape;
+        let kernel_size = self.weights.shape()[2];
+        Some((
+            ((height.checked_sub(1)? * self.stride + kernel_size).checked_sub(2 * self.padding))?,
+            ((width.checked_sub(1)? * self.stride + kernel_size).checked_sub(2 * self.padding))?,
+        ))
+    }
+
+    // Calls `visit(input_index, output_index, kernel_index)` for every input pixel, output
+    // pixel and kernel tap that are connected, with indexes into the flat buffers.
+    fn for_each_tap(&self, mut visit: impl FnMut([usize; 3], [usize; 3], [usize; 4])) {
+        let [channels, height, width] = self.input_shape;
+        let (_, filters, kernel_size, _) = self.weights.dim();
+        let (output_height, output_width) = self.output_size();
+
+        for channel in 0..channels {
+            for row in 0..height {
+                for col in 0..width {
+                    for kernel_row in 0..kernel_size {
+                        let output_row =
+                            (row * self.stride + kernel_row).wrapping_sub(self.padding);
+                        if output_row >= output_height {
+                            continue;
+                        }
+
+                        for kernel_col in 0..kernel_size {
+                            let output_col =
+                                (col * self.stride + kernel_col).wrapping_sub(self.padding);
+                            if output_col >= output_width {
+                                continue;
+                            }
+
+                            for filter in 0..filters {
+                                visit(
+                                    [channel, row, col],
+                                    [filter, output_row, output_col],
+                                    [channel, filter, kernel_row, kernel_col],
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl NuralNetworkLayer for ConvTranspose2dLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        let [_, height, width] = self.input_shape;
+        let (output_height, output_width) = self.output_size();
+        let (channels, filters, kernel_size, _) = self.weights.dim();
+        2 * channels * height * width * filters * kernel_size * kernel_size
+            + filters * output_height * output_width
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let (output_height, output_width) = self.output_size();
+        let input = ArrayView3::from_shape(self.input_shape, input)
+            .expect("ConvTranspose2dLayer input size mismatch");
+
+        let mut output = Array3::<Float>::zeros((self.bias.len(), output_height, output_width));
+        for (filter, mut filter_output) in output.outer_iter_mut().enumerate() {
+            filter_output.fill(self.bias[filter]);
+        }
+        self.for_each_tap(|input_index, output_index, kernel_index| {
+            output[output_index] += input[input_index] * self.weights[kernel_index];
+        });
+
+        output.into_iter().collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let (output_height, output_width) = self.output_size();
+        let input = ArrayView3::from_shape(self.input_shape, input).unwrap();
+        let output_gradient = ArrayView3::from_shape(
+            (self.bias.len(), output_height, output_width),
+            output_gradient,
+        )
+        .unwrap();
+
+        let mut input_gradient = Array3::<Float>::zeros(input.raw_dim());
+        let mut weights_gradient = Array4::<Float>::zeros(self.weights.raw_dim());
+        self.for_each_tap(|input_index, output_index, kernel_index| {
+            input_gradient[input_index] +=
+                output_gradient[output_index] * self.weights[kernel_index];
+            weights_gradient[kernel_index] += output_gradient[output_index] * input[input_index];
+        });
+
+        let bias_gradient = output_gradient
+            .outer_iter()
+            .map(|filter_gradient| filter_gradient.sum())
+            .collect::<Array1<Float>>();
+
+        let parameter_gradient = bias_gradient
+            .iter()
+            .chain(weights_gradient.iter())
+            .copied()
+            .collect();
+        (input_gradient.into_iter().collect(), parameter_gradient)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        if input_shape != self.input_shape {
+            return Err(format!(
+                "ConvTranspose2dLayer expects input shape {:?}, got {:?}",
+                self.input_shape, input_shape
+            ));
+        }
+
+        match self.checked_output_size() {
+            Some((output_height, output_width)) => {
+                Ok(vec![self.bias.len(), output_height, output_width])
+            }
+            None => Err(format!(
+                "ConvTranspose2dLayer padding {} crops away the whole output of input shape {:?}",
+                self.padding, self.input_shape
+            )),
+        }
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![self.bias.view().into_dyn(), self.weights.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.weights.view_mut().into_dyn(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let input = (0..2 * 3 * 3)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        for (stride, padding) in [(1, 0), (2, 0), (2, 1)] {
+            let mut layer =
+                ConvTranspose2dLayer::new([2, 3, 3], 3, 3, stride).with_padding(padding);
+            let check = gradient_check(&mut layer, &input);
+            assert!(
+                check.passed(),
+                "stride {}, padding {}: input error {}, parameter error {}",
+                stride,
+                padding,
+                check.input_error,
+                check.parameter_error
+            );
+        }
+    }
+
+    #[test]
+    fn output_shape_follows_stride_and_padding() {
+        let layer = ConvTranspose2dLayer::new([2, 3, 3], 3, 3, 2).with_padding(1);
+        assert_eq!(layer.output_shape(&[2, 3, 3]), Ok(vec![3, 5, 5]));
+        assert!(layer.output_shape(&[2, 4, 4]).is_err());
+        let layer = ConvTranspose2dLayer::new([1, 1, 1], 1, 1, 1).with_padding(1);
+        assert!(layer.output_shape(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Stride must be at least 1")]
+    fn zero_stride_is_rejected() {
+        ConvTranspose2dLayer::new([1, 3, 3], 1, 3, 0);
+    }
+}
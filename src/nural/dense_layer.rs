@@ -1,5 +1,8 @@
-﻿use crate::nural::nural_network_layer::NuralNetworkLayer;
-use ndarray::Array2;
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::regularization::Regularization;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array2, ArrayView2, ArrayViewD, ArrayViewMutD};
 use rand::Rng;
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -7,8 +10,18 @@ use std::any::Any;
 use std::fmt;
 
 pub struct DenseLayer {
-    bias: Array2<f64>,
-    weights: Array2<f64>,
+    bias: Array2<Float>,
+    regularization: Regularization,
+    sparsity_pattern: Option<SparsityPattern>,
+    weights: Array2<Float>,
+}
+
+// The weights left after pruning, row by row: the weights of row `r` are at the columns
+// `columns[row_offsets[r]..row_offsets[r + 1]]`. Every other weight is zero and stays zero.
+#[derive(Clone, Deserialize, Serialize)]
+struct SparsityPattern {
+    columns: Vec<usize>,
+    row_offsets: Vec<usize>,
 }
 
 impl DenseLayer {
@@ -16,9 +29,120 @@ impl DenseLayer {
         let mut rng = rand::rng();
         DenseLayer {
             bias: Array2::from_shape_fn((outputs, 1), |_| rng.random_range(-1.0..1.0)),
+            regularization: Regularization::default(),
+            sparsity_pattern: None,
             weights: Array2::from_shape_fn((outputs, inputs), |_| rng.random_range(-1.0..1.0)),
         }
     }
+
+    // A layer with the given (outputs × inputs) weights and one bias per output.
+    pub fn from_weights(weights: Array2<Float>, bias: Vec<Float>) -> DenseLayer {
+        assert_eq!(
+            weights.nrows(),
+            bias.len(),
+            "DenseLayer needs one bias per output"
+        );
+        DenseLayer {
+            bias: Array2::from_shape_vec((bias.len(), 1), bias).unwrap(),
+            regularization: Regularization::default(),
+            sparsity_pattern: None,
+            weights,
+        }
+    }
+
+    // Applies the regularization to the bias as well as the weights.
+    pub fn with_bias_regularization(mut self, include_bias: bool) -> DenseLayer {
+        self.regularization.include_bias = include_bias;
+        self
+    }
+
+    pub fn with_l1(mut self, l1: Float) -> DenseLayer {
+        self.regularization.l1 = l1;
+        self
+    }
+
+    pub fn with_l2(mut self, l2: Float) -> DenseLayer {
+        self.regularization.l2 = l2;
+        self
+    }
+
+    pub fn with_max_norm(mut self, max_norm: Float) -> DenseLayer {
+        self.regularization.max_norm = Some(max_norm);
+        self
+    }
+
+    pub fn with_weight_decay(mut self, weight_decay: Float) -> DenseLayer {
+        self.regularization.weight_decay = weight_decay;
+        self
+    }
+
+    // Zeroes every weight whose magnitude is at most the threshold and keeps it at zero in
+    // later training. Forward then only visits the remaining weights. Pruning again with a
+    // higher threshold removes more weights, pruned weights are never restored.
+    pub fn prune(&mut self, threshold: Float) {
+        let mut columns = vec![];
+        let mut row_offsets = vec![0];
+        for row in self.weights.rows_mut() {
+            for (column, weight) in row.into_iter().enumerate() {
+                if weight.abs() > threshold {
+                    columns.push(column);
+                } else {
+                    *weight = 0.0;
+                }
+            }
+            row_offsets.push(columns.len());
+        }
+        self.sparsity_pattern = Some(SparsityPattern {
+            columns,
+            row_offsets,
+        });
+    }
+
+    // Replaces the weights and biases, possibly with a different shape. Keeps the
+    // regularization and drops the pruning.
+    pub fn set_weights(&mut self, weights: Array2<Float>, bias: Vec<Float>) {
+        assert_eq!(
+            weights.nrows(),
+            bias.len(),
+            "DenseLayer needs one bias per output"
+        );
+        self.bias = Array2::from_shape_vec((bias.len(), 1), bias).unwrap();
+        self.sparsity_pattern = None;
+        self.weights = weights;
+    }
+
+    // Share of the weights removed by pruning.
+    pub fn sparsity(&self) -> Float {
+        match &self.sparsity_pattern {
+            Some(sparsity_pattern) => {
+                1.0 - sparsity_pattern.columns.len() as Float / self.weights.len() as Float
+            }
+            None => 0.0,
+        }
+    }
+
+    // Number of weights that are not pruned.
+    pub fn weight_count(&self) -> usize {
+        self.sparsity_pattern
+            .as_ref()
+            .map_or(self.weights.len(), |sparsity_pattern| {
+                sparsity_pattern.columns.len()
+            })
+    }
+
+    pub fn weights(&self) -> &Array2<Float> {
+        &self.weights
+    }
+}
+
+impl SparsityPattern {
+    fn row(&self, row: usize) -> &[usize] {
+        &self.columns[self.row_offsets[row]..self.row_offsets[row + 1]]
+    }
+
+    fn rows(&self) -> usize {
+        self.row_offsets.len() - 1
+    }
 }
 
 impl NuralNetworkLayer for DenseLayer {
@@ -26,38 +150,137 @@ impl NuralNetworkLayer for DenseLayer {
         self
     }
 
-    fn backward(
-        &mut self,
-        input: &[f64],
-        _output: &[f64],
-        output_gradient: &[f64],
-        learning_rate: f64,
-    ) -> Vec<f64> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // The multiply-adds of the unpruned weights and one add per bias.
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        2 * self.weight_count() + self.bias.len()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        if let Some(sparsity_pattern) = &self.sparsity_pattern {
+            return (0..sparsity_pattern.rows())
+                .map(|row| {
+                    sparsity_pattern
+                        .row(row)
+                        .iter()
+                        .map(|&column| self.weights[[row, column]] * input[column])
+                        .sum::<Float>()
+                        + self.bias[[row, 0]]
+                })
+                .collect();
+        }
+
+        let input_vec = Array2::from_shape_vec((input.len(), 1), input.to_vec()).unwrap();
+        let (output, _) = (&self.weights.dot(&input_vec) + &self.bias).into_raw_vec_and_offset();
+        output
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
         let output_gradient_vec =
             Array2::from_shape_vec((output_gradient.len(), 1), output_gradient.to_vec()).unwrap();
         let input_vec = Array2::from_shape_vec((input.len(), 1), input.to_vec()).unwrap();
-        let weights_gradient_mx = output_gradient_vec.dot(&input_vec.t());
+        let mut weights_gradient_mx =
+            output_gradient_vec.dot(&input_vec.t()) + self.regularization.gradient(&self.weights);
+        // Pruned weights get no gradient, so no update can bring them back.
+        if let Some(sparsity_pattern) = &self.sparsity_pattern {
+            let mut masked_gradient_mx = Array2::zeros(weights_gradient_mx.raw_dim());
+            for row in 0..sparsity_pattern.rows() {
+                for &column in sparsity_pattern.row(row) {
+                    masked_gradient_mx[[row, column]] = weights_gradient_mx[[row, column]];
+                }
+            }
+            weights_gradient_mx = masked_gradient_mx;
+        }
         let input_gradient_mx = self.weights.t().dot(&output_gradient_vec);
-
-        self.weights = &self.weights - &weights_gradient_mx * learning_rate;
-        self.bias = &self.bias - output_gradient_vec * learning_rate;
+        let bias_gradient_mx = if self.regularization.include_bias {
+            output_gradient_vec + self.regularization.gradient(&self.bias)
+        } else {
+            output_gradient_vec
+        };
 
         let (input_gradient, _) = input_gradient_mx.into_raw_vec_and_offset();
-        input_gradient
+        let parameter_gradient = bias_gradient_mx
+            .iter()
+            .chain(weights_gradient_mx.iter())
+            .copied()
+            .collect();
+        (input_gradient, parameter_gradient)
     }
 
-    fn forward(&self, input: &[f64]) -> Vec<f64> {
-        let input_vec = Array2::from_shape_vec((input.len(), 1), input.to_vec()).unwrap();
-        let (output, _) = (&self.weights.dot(&input_vec) + &self.bias).into_raw_vec_and_offset();
-        output
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape {
+            [inputs] if *inputs == self.weights.ncols() => Ok(vec![self.weights.nrows()]),
+            _ => Err(format!(
+                "DenseLayer expects input shape [{}], got {:?}",
+                self.weights.ncols(),
+                input_shape
+            )),
+        }
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.bias.len() + self.weight_count()
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![self.bias.view().into_dyn(), self.weights.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.weights.view_mut().into_dyn(),
+        ]
+    }
+
+    fn penalty(&self) -> Float {
+        let bias_penalty = if self.regularization.include_bias {
+            self.regularization.penalty(&self.bias)
+        } else {
+            0.0
+        };
+        self.regularization.penalty(&self.weights) + bias_penalty
+    }
+
+    // Plain gradient descent followed by the decoupled weight decay and the max-norm constraint.
+    fn update(&mut self, parameter_gradient: &ParameterGradient, learning_rate: Float) {
+        let parameter_gradient = parameter_gradient.to_dense(self.parameter_len());
+        let (bias_gradient, weights_gradient) = parameter_gradient.split_at(self.bias.len());
+        self.bias.scaled_add(
+            -learning_rate,
+            &ArrayView2::from_shape(self.bias.raw_dim(), bias_gradient).unwrap(),
+        );
+        self.weights.scaled_add(
+            -learning_rate,
+            &ArrayView2::from_shape(self.weights.raw_dim(), weights_gradient).unwrap(),
+        );
+
+        self.regularization.decay(&mut self.weights, learning_rate);
+        self.regularization.constrain(&mut self.weights);
+        if self.regularization.include_bias {
+            self.regularization.decay(&mut self.bias, learning_rate);
+        }
     }
 }
 
 #[derive(Deserialize, Serialize)]
 struct DenseLayerData {
-    bias: Vec<f64>,
+    bias: Vec<Float>,
     bias_shape: [usize; 2],
-    weights: Vec<f64>,
+    #[serde(default)]
+    regularization: Regularization,
+    // When set, `weights` only holds the weights left after pruning, in pattern order.
+    #[serde(default)]
+    sparsity_pattern: Option<SparsityPattern>,
+    weights: Vec<Float>,
     weights_shape: [usize; 2],
 }
 
@@ -66,11 +289,25 @@ impl Serialize for DenseLayer {
     where
         S: Serializer,
     {
-        let (bias, _) = self.bias.clone().into_raw_vec_and_offset();
-        let (weights, _) = self.weights.clone().into_raw_vec_and_offset();
+        // Updates can leave the arrays in column-major layout, so copy them out in logical
+        // (row-major) order rather than taking the raw buffer.
+        let bias = self.bias.iter().copied().collect();
+        let weights = match &self.sparsity_pattern {
+            Some(sparsity_pattern) => (0..sparsity_pattern.rows())
+                .flat_map(|row| {
+                    sparsity_pattern
+                        .row(row)
+                        .iter()
+                        .map(move |&column| self.weights[[row, column]])
+                })
+                .collect(),
+            None => self.weights.iter().copied().collect(),
+        };
         let data = DenseLayerData {
             bias,
             bias_shape: self.bias.shape()[0..=1].try_into().unwrap(),
+            regularization: self.regularization.clone(),
+            sparsity_pattern: self.sparsity_pattern.clone(),
             weights,
             weights_shape: self.weights.shape()[0..=1].try_into().unwrap(),
         };
@@ -102,9 +339,118 @@ impl<'a> Deserialize<'a> for DenseLayer {
         }
 
         let data = deserializer.deserialize_newtype_struct("Data", DataVisitor)?;
+        let weights = match &data.sparsity_pattern {
+            Some(sparsity_pattern) => {
+                let mut weights = Array2::zeros(data.weights_shape);
+                let mut values = data.weights.iter();
+                for row in 0..sparsity_pattern.rows() {
+                    for &column in sparsity_pattern.row(row) {
+                        weights[[row, column]] = *values.next().ok_or_else(|| {
+                            serde::de::Error::custom("DenseLayer sparsity pattern has no weight")
+                        })?;
+                    }
+                }
+                weights
+            }
+            None => Array2::from_shape_vec(data.weights_shape, data.weights).unwrap(),
+        };
         Ok(DenseLayer {
             bias: Array2::from_shape_vec(data.bias_shape, data.bias).unwrap(),
-            weights: Array2::from_shape_vec(data.weights_shape, data.weights).unwrap(),
+            regularization: data.regularization,
+            sparsity_pattern: data.sparsity_pattern,
+            weights,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    // Weights well away from zero, so the finite differences never cross the L1 kink.
+    fn signed_layer() -> DenseLayer {
+        let weights = Array2::from_shape_fn((2, 3), |(row, column)| {
+            let weight = 0.5 + 0.25 * (row * 3 + column) as Float;
+            if (row + column) % 2 == 0 {
+                weight
+            } else {
+                -weight
+            }
+        });
+        DenseLayer::from_weights(weights, vec![0.75, -0.5])
+    }
+
+    #[test]
+    fn pruned_layers_round_trip() {
+        let mut layer = signed_layer();
+        layer.prune(1.0);
+        let serialized_bytes = serde_cbor::to_vec(&layer).unwrap();
+        let loaded_layer = serde_cbor::from_slice::<DenseLayer>(&serialized_bytes).unwrap();
+        assert_eq!(loaded_layer.weights, layer.weights);
+        assert_eq!(loaded_layer.bias, layer.bias);
+        assert_eq!(loaded_layer.weight_count(), layer.weight_count());
+        // Only the weights left after pruning are saved.
+        let data = serde_cbor::from_slice::<DenseLayerData>(&serialized_bytes).unwrap();
+        assert_eq!(data.weights.len(), layer.weight_count());
+    }
+
+    #[test]
+    fn missing_pruned_weights_are_an_error() {
+        let mut layer = signed_layer();
+        layer.prune(1.0);
+        let mut data =
+            serde_cbor::from_slice::<DenseLayerData>(&serde_cbor::to_vec(&layer).unwrap()).unwrap();
+        data.weights.pop();
+        let serialized_bytes = serde_cbor::to_vec(&data).unwrap();
+        assert!(serde_cbor::from_slice::<DenseLayer>(&serialized_bytes).is_err());
+    }
+
+    #[test]
+    fn penalty_gradients_match_finite_differences() {
+        let mut layer = signed_layer()
+            .with_l1(0.1)
+            .with_l2(0.2)
+            .with_bias_regularization(true);
+        let input: Vec<Float> = (0..3).map(|index| (index as Float * 0.37).sin()).collect();
+        let check = gradient_check(&mut layer, &input);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+
+    #[test]
+    fn weight_decay_shrinks_the_weights() {
+        let mut layer = signed_layer().with_weight_decay(0.1);
+        let gradient = ParameterGradient::Dense(vec![0.0; layer.parameter_len()]);
+        layer.update(&gradient, 0.5);
+        assert_eq!(layer.weights, signed_layer().weights * 0.95);
+        assert_eq!(layer.bias, signed_layer().bias);
+
+        let mut layer = signed_layer()
+            .with_weight_decay(0.1)
+            .with_bias_regularization(true);
+        let gradient = ParameterGradient::Dense(vec![0.0; layer.parameter_len()]);
+        layer.update(&gradient, 0.5);
+        assert_eq!(layer.bias, signed_layer().bias * 0.95);
+    }
+
+    #[test]
+    fn max_norm_rescales_the_rows_over_the_limit() {
+        let weights = Array2::from_shape_vec((2, 2), vec![3.0, 4.0, 0.3, 0.4]).unwrap();
+        let mut layer = DenseLayer::from_weights(weights, vec![0.0, 0.0]).with_max_norm(1.0);
+        let gradient = ParameterGradient::Dense(vec![0.0; layer.parameter_len()]);
+        layer.update(&gradient, 0.1);
+        let norms: Vec<Float> = layer
+            .weights
+            .rows()
+            .into_iter()
+            .map(|row| row.dot(&row).sqrt())
+            .collect();
+        assert!((norms[0] - 1.0).abs() < 1e-12, "norm {}", norms[0]);
+        assert_eq!(layer.weights.row(1).to_vec(), vec![0.3, 0.4]);
+    }
+}
@@ -0,0 +1,119 @@
+﻿use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array2, ArrayViewD, ArrayViewMutD};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Looks up a trainable vector for every id in the input, ids are passed as whole numbered
+// floats. The parameter gradient only holds the rows of the ids seen in a sample, so training
+// costs in proportion to the sample, not to the number of ids.
+#[derive(Deserialize, Serialize)]
+pub struct EmbeddingLayer {
+    #[serde(with = "array_serde")]
+    weights: Array2<Float>,
+}
+
+impl EmbeddingLayer {
+    pub fn new(ids: usize, dimensions: usize) -> EmbeddingLayer {
+        let mut rng = rand::rng();
+        EmbeddingLayer {
+            weights: Array2::from_shape_fn((ids, dimensions), |_| rng.random_range(-1.0..1.0)),
+        }
+    }
+
+    fn id(&self, value: Float) -> usize {
+        assert!(
+            value >= 0.0 && value.fract() == 0.0 && (value as usize) < self.weights.nrows(),
+            "Embedding id {} out of range 0..{}",
+            value,
+            self.weights.nrows()
+        );
+        value as usize
+    }
+}
+
+impl NuralNetworkLayer for EmbeddingLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        input
+            .iter()
+            .flat_map(|&value| self.weights.row(self.id(value)).to_vec())
+            .collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let dimensions = self.weights.ncols();
+        let mut weights_gradient = ParameterGradient::rows(dimensions);
+        for (&value, gradient) in input.iter().zip(output_gradient.chunks(dimensions)) {
+            weights_gradient.add_row(self.id(value), gradient);
+        }
+
+        // Ids are categorical, there is no gradient to pass to the previous layer.
+        (vec![0.0; input.len()], weights_gradient)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape {
+            [ids] => Ok(vec![*ids, self.weights.ncols()]),
+            _ => Err(format!(
+                "EmbeddingLayer expects input shape [ids], got {:?}",
+                input_shape
+            )),
+        }
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![self.weights.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![self.weights.view_mut().into_dyn()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gradient_only_updates_the_rows_of_the_sample() {
+        let mut layer = EmbeddingLayer::new(1000, 4);
+        let weights = layer.weights.clone();
+        let input = [3.0, 7.0, 3.0];
+        let output = layer.forward(&input);
+        let output_gradient = (0..output.len())
+            .map(|index| index as Float * 0.1)
+            .collect::<Vec<Float>>();
+
+        let (_, parameter_gradient) = layer.gradient(&input, &output, &output_gradient);
+        assert_eq!(parameter_gradient.values().count(), 2 * 4);
+        layer.update(&parameter_gradient, 1.0);
+
+        let dense_gradient = parameter_gradient.to_dense(layer.parameter_len());
+        for (index, ((&val, &old_val), &gradient_val)) in layer
+            .weights
+            .iter()
+            .zip(weights.iter())
+            .zip(dense_gradient.iter())
+            .enumerate()
+        {
+            assert_eq!(val, old_val - gradient_val, "weight {}", index);
+        }
+        assert_eq!(dense_gradient[3 * 4], 0.8);
+    }
+}
@@ -0,0 +1,53 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+#[derive(Deserialize, Serialize)]
+pub struct FlattenLayer;
+
+impl FlattenLayer {
+    pub fn new() -> FlattenLayer {
+        FlattenLayer
+    }
+}
+
+impl NuralNetworkLayer for FlattenLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        input.to_vec()
+    }
+
+    fn gradient(
+        &self,
+        _input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        (output_gradient.to_vec(), ParameterGradient::default())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        Ok(vec![input_shape.iter().product()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_shape_is_flat() {
+        let layer = FlattenLayer::new();
+        assert_eq!(layer.output_shape(&[2, 3, 4]), Ok(vec![24]));
+        assert_eq!(layer.output_shape(&[5]), Ok(vec![5]));
+    }
+}
@@ -0,0 +1,2 @@
+﻿// The float type of all network values.
+pub type Float = f64;
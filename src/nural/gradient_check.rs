@@ -0,0 +1,120 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use rand::Rng;
+
+// Largest relative differences between the gradients returned by `gradient` and central
+// finite differences of a random projection of the layer output (plus the layer's penalty
+// for the parameters).
+pub struct GradientCheck {
+    pub input_error: Float,
+    pub parameter_error: Float,
+}
+
+impl GradientCheck {
+    // Finite differences lose about a third of the float's digits, so the step and the
+    // accepted error follow the precision of `Float`.
+    pub fn step() -> Float {
+        Float::EPSILON.cbrt()
+    }
+
+    pub fn tolerance() -> Float {
+        Float::EPSILON.cbrt()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.input_error <= GradientCheck::tolerance()
+            && self.parameter_error <= GradientCheck::tolerance()
+    }
+}
+
+pub fn gradient_check(layer: &mut dyn NuralNetworkLayer, input: &[Float]) -> GradientCheck {
+    let step = GradientCheck::step();
+
+    let mut rng = rand::rng();
+    let output = layer.forward(input);
+    let projection = output
+        .iter()
+        .map(|_| rng.random_range(-1.0..1.0))
+        .collect::<Vec<Float>>();
+    let loss = |layer: &dyn NuralNetworkLayer, input: &[Float]| -> Float {
+        layer
+            .forward(input)
+            .iter()
+            .zip(projection.iter())
+            .map(|(output_val, projection_val)| output_val * projection_val)
+            .sum::<Float>()
+            + layer.penalty()
+    };
+    let (input_gradient, parameter_gradient) = layer.gradient(input, &output, &projection);
+    let parameter_gradient = parameter_gradient
+        .to_dense(layer.parameter_len())
+        .into_owned();
+
+    let mut shifted_input = input.to_vec();
+    let mut input_error: Float = 0.0;
+    for index in 0..input.len() {
+        shifted_input[index] = input[index] + step;
+        let loss_up = loss(layer, &shifted_input);
+        shifted_input[index] = input[index] - step;
+        let loss_down = loss(layer, &shifted_input);
+        shifted_input[index] = input[index];
+
+        let numeric_gradient = (loss_up - loss_down) / (2.0 * step);
+        input_error = input_error.max(relative_error(input_gradient[index], numeric_gradient));
+    }
+
+    let mut parameter_error: Float = 0.0;
+    let mut parameter_index = 0;
+    for array_index in 0..layer.parameters().len() {
+        for value_index in 0..layer.parameters()[array_index].len() {
+            let value = shift_parameter(layer, array_index, value_index, step);
+            let loss_up = loss(layer, input);
+            shift_parameter(layer, array_index, value_index, -2.0 * step);
+            let loss_down = loss(layer, input);
+            set_parameter(layer, array_index, value_index, value);
+
+            let numeric_gradient = (loss_up - loss_down) / (2.0 * step);
+            parameter_error = parameter_error.max(relative_error(
+                parameter_gradient[parameter_index],
+                numeric_gradient,
+            ));
+            parameter_index += 1;
+        }
+    }
+
+    GradientCheck {
+        input_error,
+        parameter_error,
+    }
+}
+
+// Relative to the gradient's size, absolute for gradients below one.
+pub fn relative_error(gradient: Float, numeric_gradient: Float) -> Float {
+    (gradient - numeric_gradient).abs() / gradient.abs().max(numeric_gradient.abs()).max(1.0)
+}
+
+// Adds the shift to one parameter value and returns the value before.
+fn shift_parameter(
+    layer: &mut dyn NuralNetworkLayer,
+    array_index: usize,
+    value_index: usize,
+    shift: Float,
+) -> Float {
+    let mut parameters = layer.parameters_mut();
+    let value = parameters[array_index].iter_mut().nth(value_index).unwrap();
+    let previous = *value;
+    *value += shift;
+    previous
+}
+
+fn set_parameter(
+    layer: &mut dyn NuralNetworkLayer,
+    array_index: usize,
+    value_index: usize,
+    value: Float,
+) {
+    *layer.parameters_mut()[array_index]
+        .iter_mut()
+        .nth(value_index)
+        .unwrap() = value;
+}
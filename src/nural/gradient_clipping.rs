@@ -0,0 +1,182 @@
+﻿use crate::nural::float::Float;
+use crate::nural::parameter_gradient::ParameterGradient;
+use serde::{Deserialize, Serialize};
+
+// Limits the parameter gradients of one training step before they are applied. Value
+// clipping runs first, then every layer is scaled down to the layer norm and finally all
+// layers together are scaled down to the global norm.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct GradientClipping {
+    global_norm: Option<Float>,
+    layer_norm: Option<Float>,
+    value: Option<Float>,
+}
+
+impl GradientClipping {
+    pub fn new() -> GradientClipping {
+        GradientClipping::default()
+    }
+
+    pub fn with_global_norm(mut self, global_norm: Float) -> GradientClipping {
+        self.global_norm = Some(global_norm);
+        self
+    }
+
+    pub fn with_layer_norm(mut self, layer_norm: Float) -> GradientClipping {
+        self.layer_norm = Some(layer_norm);
+        self
+    }
+
+    pub fn with_value(mut self, value: Float) -> GradientClipping {
+        self.value = Some(value);
+        self
+    }
+
+    // Clips the gradients of all layers in place and returns their global norm before clipping.
+    pub fn clip(&self, layer_gradients: &mut [ParameterGradient]) -> Float {
+        let global_norm = norm(
+            layer_gradients
+                .iter()
+                .flat_map(|gradient| gradient.values()),
+        );
+
+        if let Some(value) = self.value {
+            layer_gradients
+                .iter_mut()
+                .flat_map(|gradient| gradient.values_mut())
+                .for_each(|gradient_val| *gradient_val = gradient_val.clamp(-value, value));
+        }
+
+        if let Some(layer_norm) = self.layer_norm {
+            for layer_gradient in layer_gradients.iter_mut() {
+                let layer_gradient_norm = norm(layer_gradient.values());
+                scale_to_norm(layer_gradient.values_mut(), layer_gradient_norm, layer_norm);
+            }
+        }
+
+        if let Some(max_global_norm) = self.global_norm {
+            let clipped_norm = norm(
+                layer_gradients
+                    .iter()
+                    .flat_map(|gradient| gradient.values()),
+            );
+            scale_to_norm(
+                layer_gradients
+                    .iter_mut()
+                    .flat_map(|gradient| gradient.values_mut()),
+                clipped_norm,
+                max_global_norm,
+            );
+        }
+
+        global_norm
+    }
+}
+
+fn norm<'a>(values: impl Iterator<Item = &'a Float>) -> Float {
+    values.map(|value| value * value).sum::<Float>().sqrt()
+}
+
+fn scale_to_norm<'a>(values: impl Iterator<Item = &'a mut Float>, norm: Float, max_norm: Float) {
+    if norm > max_norm {
+        values.for_each(|value| *value *= max_norm / norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two layers with norms 5 and 10, global norm √125.
+    fn layer_gradients() -> Vec<ParameterGradient> {
+        vec![
+            ParameterGradient::Dense(vec![3.0, -4.0]),
+            ParameterGradient::Dense(vec![0.0, 6.0, -8.0]),
+        ]
+    }
+
+    fn layer_norms(layer_gradients: &[ParameterGradient]) -> Vec<Float> {
+        layer_gradients
+            .iter()
+            .map(|gradient| norm(gradient.values()))
+            .collect()
+    }
+
+    #[test]
+    fn value_clipping_clamps_every_value() {
+        let mut layer_gradients = layer_gradients();
+        GradientClipping::new()
+            .with_value(5.0)
+            .clip(&mut layer_gradients);
+        assert_eq!(
+            layer_gradients,
+            vec![
+                ParameterGradient::Dense(vec![3.0, -4.0]),
+                ParameterGradient::Dense(vec![0.0, 5.0, -5.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn layer_norm_clipping_scales_each_layer_over_the_limit() {
+        let mut layer_gradients = layer_gradients();
+        GradientClipping::new()
+            .with_layer_norm(6.0)
+            .clip(&mut layer_gradients);
+        assert_eq!(
+            layer_gradients[0],
+            ParameterGradient::Dense(vec![3.0, -4.0])
+        );
+        let norms = layer_norms(&layer_gradients);
+        assert!((norms[1] - 6.0).abs() < 1e-12, "layer norm {}", norms[1]);
+    }
+
+    #[test]
+    fn global_norm_clipping_scales_all_layers_together() {
+        let mut layer_gradients = layer_gradients();
+        GradientClipping::new()
+            .with_global_norm(5.0)
+            .clip(&mut layer_gradients);
+        let norms = layer_norms(&layer_gradients);
+        let global_norm = norms.iter().map(|norm| norm * norm).sum::<Float>().sqrt();
+        assert!(
+            (global_norm - 5.0).abs() < 1e-12,
+            "global norm {}",
+            global_norm
+        );
+        // The layers keep their relative size.
+        assert!((norms[1] / norms[0] - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn clip_returns_the_norm_before_clipping() {
+        let clipping = GradientClipping::new()
+            .with_value(1.0)
+            .with_layer_norm(1.0)
+            .with_global_norm(1.0);
+        let mut layer_gradients = layer_gradients();
+        let global_norm = clipping.clip(&mut layer_gradients);
+        assert!((global_norm - (125.0 as Float).sqrt()).abs() < 1e-12);
+        assert!(
+            norm(
+                layer_gradients
+                    .iter()
+                    .flat_map(|gradient| gradient.values())
+            ) <= 1.0
+        );
+    }
+
+    #[test]
+    fn row_gradients_are_clipped_like_dense_ones() {
+        let mut rows = ParameterGradient::rows(2);
+        rows.add_row(3, &[0.0, 6.0]);
+        rows.add_row(7, &[-8.0, 0.0]);
+        let mut layer_gradients = vec![rows];
+        let global_norm = GradientClipping::new()
+            .with_layer_norm(5.0)
+            .clip(&mut layer_gradients);
+        assert_eq!(global_norm, 10.0);
+        assert_eq!(layer_gradients[0].to_dense(16)[6..8], [0.0, 3.0]);
+        assert_eq!(layer_gradients[0].to_dense(16)[14..16], [-4.0, 0.0]);
+    }
+}
@@ -0,0 +1,255 @@
+﻿use crate::nural::activation_fns::{SIGMOID, TANH};
+use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::recurrent::*;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{concatenate, s, Array1, Array2, ArrayViewD, ArrayViewMutD, Axis};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Gate rows are stacked in the order update, reset, candidate. The reset gate is applied
+// after the hidden weights, h = (1 - z) * n + z * h_prev with n = tanh(Wx + r * (Uh) + b).
+#[derive(Deserialize, Serialize)]
+pub struct GruLayer {
+    #[serde(with = "array_serde")]
+    bias: Array1<Float>,
+    #[serde(with = "array_serde")]
+    hidden_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    input_weights: Array2<Float>,
+    return_sequences: bool,
+    truncation: Option<usize>,
+}
+
+struct GruStep {
+    candidate: Array1<Float>,
+    candidate_hidden: Array1<Float>,
+    hidden: Array1<Float>,
+    reset_gate: Array1<Float>,
+    update_gate: Array1<Float>,
+}
+
+impl GruLayer {
+    pub fn new(inputs: usize, outputs: usize) -> GruLayer {
+        GruLayer {
+            bias: init_bias(3 * outputs, outputs),
+            hidden_weights: init_weights(3 * outputs, outputs, outputs),
+            input_weights: init_weights(3 * outputs, inputs, outputs),
+            return_sequences: false,
+            truncation: None,
+        }
+    }
+
+    pub fn with_return_sequences(mut self, return_sequences: bool) -> GruLayer {
+        self.return_sequences = return_sequences;
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: usize) -> GruLayer {
+        self.truncation = Some(truncation);
+        self
+    }
+
+    fn outputs(&self) -> usize {
+        self.hidden_weights.ncols()
+    }
+
+    fn forward_steps(&self, input: &[Float]) -> Vec<GruStep> {
+        let outputs = self.outputs();
+        let mut steps = vec![GruStep {
+            candidate: Array1::zeros(outputs),
+            candidate_hidden: Array1::zeros(outputs),
+            hidden: Array1::zeros(outputs),
+            reset_gate: Array1::zeros(outputs),
+            update_gate: Array1::zeros(outputs),
+        }];
+
+        for step_input in sequence(input, self.input_weights.ncols()) {
+            let previous = &steps.last().unwrap().hidden;
+            let input_gates = self.input_weights.dot(&step_input) + &self.bias;
+            let hidden_gates = self.hidden_weights.dot(previous);
+
+            let update_gate = (&input_gates.slice(s![0..outputs])
+                + &hidden_gates.slice(s![0..outputs]))
+                .mapv(SIGMOID.fx);
+            let reset_gate = (&input_gates.slice(s![outputs..2 * outputs])
+                + &hidden_gates.slice(s![outputs..2 * outputs]))
+                .mapv(SIGMOID.fx);
+            let candidate_hidden = hidden_gates.slice(s![2 * outputs..]).to_owned();
+            let candidate = (&input_gates.slice(s![2 * outputs..])
+                + &reset_gate * &candidate_hidden)
+                .mapv(TANH.fx);
+
+            let hidden = update_gate.mapv(|z| 1.0 - z) * &candidate + &update_gate * previous;
+
+            steps.push(GruStep {
+                candidate,
+                candidate_hidden,
+                hidden,
+                reset_gate,
+                update_gate,
+            });
+        }
+        steps
+    }
+}
+
+impl NuralNetworkLayer for GruLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // The matrix products and bias adds of every step, the gate updates are left out.
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let steps = input_shape.first().copied().unwrap_or(1);
+        steps * (2 * (self.input_weights.len() + self.hidden_weights.len()) + self.bias.len())
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let states = self
+            .forward_steps(input)
+            .into_iter()
+            .map(|step| step.hidden)
+            .collect::<Vec<_>>();
+        collect_output(&states, self.return_sequences)
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let outputs = self.outputs();
+        let sequence = sequence(input, self.input_weights.ncols());
+        let steps = self.forward_steps(input);
+        let step_gradients = step_gradients(
+            output_gradient,
+            sequence.len(),
+            outputs,
+            self.return_sequences,
+        );
+
+        let mut bias_gradient = Array1::zeros(self.bias.raw_dim());
+        let mut hidden_weights_gradient = Array2::zeros(self.hidden_weights.raw_dim());
+        let mut input_weights_gradient = Array2::zeros(self.input_weights.raw_dim());
+        let mut input_gradient = vec![Array1::zeros(self.input_weights.ncols()); sequence.len()];
+
+        let mut hidden_gradient = Array1::<Float>::zeros(outputs);
+        for step in (0..sequence.len()).rev() {
+            let previous = &steps[step].hidden;
+            let current = &steps[step + 1];
+            hidden_gradient += &step_gradients[step];
+
+            let candidate_gradient = &hidden_gradient
+                * current.update_gate.mapv(|z| 1.0 - z)
+                * current.candidate.mapv(|n| 1.0 - n * n);
+            let update_gate_gradient = &hidden_gradient
+                * (previous - &current.candidate)
+                * current.update_gate.mapv(|z| z * (1.0 - z));
+            let reset_gate_gradient = &candidate_gradient
+                * &current.candidate_hidden
+                * current.reset_gate.mapv(|r| r * (1.0 - r));
+
+            let input_gates_gradient = concatenate![
+                Axis(0),
+                update_gate_gradient,
+                reset_gate_gradient,
+                candidate_gradient
+            ];
+            let hidden_gates_gradient = concatenate![
+                Axis(0),
+                update_gate_gradient,
+                reset_gate_gradient,
+                &candidate_gradient * &current.reset_gate
+            ];
+
+            bias_gradient += &input_gates_gradient;
+            hidden_weights_gradient += &outer(&hidden_gates_gradient, &previous.view());
+            input_weights_gradient += &outer(&input_gates_gradient, &sequence[step]);
+            input_gradient[step] = self.input_weights.t().dot(&input_gates_gradient);
+
+            hidden_gradient = if is_truncated(step, self.truncation) {
+                Array1::zeros(outputs)
+            } else {
+                &hidden_gradient * &current.update_gate
+                    + self.hidden_weights.t().dot(&hidden_gates_gradient)
+            };
+        }
+
+        let parameter_gradient = bias_gradient
+            .iter()
+            .chain(hidden_weights_gradient.iter())
+            .chain(input_weights_gradient.iter())
+            .copied()
+            .collect();
+        (
+            input_gradient.into_iter().flatten().collect(),
+            parameter_gradient,
+        )
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        sequence_output_shape(
+            input_shape,
+            self.input_weights.ncols(),
+            self.outputs(),
+            self.return_sequences,
+        )
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![
+            self.bias.view().into_dyn(),
+            self.hidden_weights.view().into_dyn(),
+            self.input_weights.view().into_dyn(),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.hidden_weights.view_mut().into_dyn(),
+            self.input_weights.view_mut().into_dyn(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let input = (0..4 * 3)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        for return_sequences in [false, true] {
+            let mut layer = GruLayer::new(3, 5).with_return_sequences(return_sequences);
+            let check = gradient_check(&mut layer, &input);
+            assert!(
+                check.passed(),
+                "input error {}, parameter error {}",
+                check.input_error,
+                check.parameter_error
+            );
+        }
+    }
+
+    #[test]
+    fn empty_sequence_returns_initial_state() {
+        let layer = GruLayer::new(3, 5);
+        assert!(layer.output_shape(&[0, 3]).is_err());
+        assert_eq!(layer.forward(&[]), vec![0.0; 5]);
+        assert_eq!(
+            layer.gradient(&[], &[0.0; 5], &[1.0; 5]).0,
+            Vec::<Float>::new()
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array1, ArrayView1, ArrayViewD, ArrayViewMutD};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Normalizes every group of `features` consecutive values on its own, a flat vector is a
+// single group while a sequence is normalized step by step. Unlike batch normalization no
+// statistics are shared between samples, so a single `predict` behaves like training.
+#[derive(Deserialize, Serialize)]
+pub struct LayerNormLayer {
+    #[serde(with = "array_serde")]
+    bias: Array1<Float>,
+    epsilon: Float,
+    #[serde(with = "array_serde")]
+    gain: Array1<Float>,
+}
+
+impl LayerNormLayer {
+    pub fn new(features: usize) -> LayerNormLayer {
+        LayerNormLayer {
+            bias: Array1::zeros(features),
+            epsilon: 1e-5,
+            gain: Array1::ones(features),
+        }
+    }
+
+    fn normalize(&self, input: &ArrayView1<Float>) -> (Array1<Float>, Float) {
+        let mean = input.mean().unwrap();
+        let variance = input.mapv(|val| (val - mean).powi(2)).mean().unwrap();
+        let deviation = (variance + self.epsilon).sqrt();
+        (input.mapv(|val| (val - mean) / deviation), deviation)
+    }
+}
+
+impl NuralNetworkLayer for LayerNormLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // Mean, variance, normalization and the affine transform per element.
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        7 * input_shape.iter().product::<usize>()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        input
+            .chunks(self.gain.len())
+            .flat_map(|group| {
+                let (normalized, _) = self.normalize(&ArrayView1::from(group));
+                normalized * &self.gain + &self.bias
+            })
+            .collect()
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let features = self.gain.len();
+        let mut bias_gradient = Array1::<Float>::zeros(features);
+        let mut gain_gradient = Array1::<Float>::zeros(features);
+        let mut input_gradient = Vec::with_capacity(input.len());
+
+        for (group, group_gradient) in input.chunks(features).zip(output_gradient.chunks(features))
+        {
+            let (normalized, deviation) = self.normalize(&ArrayView1::from(group));
+            let group_gradient = ArrayView1::from(group_gradient);
+
+            bias_gradient += &group_gradient;
+            gain_gradient += &(&group_gradient * &normalized);
+
+            let normalized_gradient = &group_gradient * &self.gain;
+            let gradient_sum = normalized_gradient.sum();
+            let gradient_dot = (&normalized_gradient * &normalized).sum();
+            input_gradient.extend(normalized_gradient.iter().zip(normalized.iter()).map(
+                |(normalized_gradient_val, normalized_val)| {
+                    (features as Float * normalized_gradient_val
+                        - gradient_sum
+                        - normalized_val * gradient_dot)
+                        / (features as Float * deviation)
+                },
+            ));
+        }
+
+        let parameter_gradient = bias_gradient
+            .iter()
+            .chain(gain_gradient.iter())
+            .copied()
+            .collect();
+        (input_gradient, parameter_gradient)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape.last() {
+            Some(&features) if features == self.gain.len() => Ok(input_shape.to_vec()),
+            _ => Err(format!(
+                "LayerNormLayer expects {} features in the last dimension, got {:?}",
+                self.gain.len(),
+                input_shape
+            )),
+        }
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![self.bias.view().into_dyn(), self.gain.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.gain.view_mut().into_dyn(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    fn sequence() -> Vec<Float> {
+        (0..3 * 4)
+            .map(|index| (index as Float * 0.37).sin() * 3.0 + 1.0)
+            .collect()
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = LayerNormLayer::new(4);
+        layer.gain = Array1::from(vec![0.5, 1.5, -1.0, 2.0]);
+        layer.bias = Array1::from(vec![0.1, -0.2, 0.3, 0.0]);
+        let check = gradient_check(&mut layer, &sequence());
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+
+    #[test]
+    fn steps_have_zero_mean_and_unit_variance() {
+        let layer = LayerNormLayer::new(4);
+        for step in layer.forward(&sequence()).chunks(4) {
+            let step = ArrayView1::from(step);
+            let mean = step.mean().unwrap();
+            let variance = step.mapv(|val| (val - mean).powi(2)).mean().unwrap();
+            assert!(mean.abs() < 1e-12, "mean {}", mean);
+            assert!((variance - 1.0).abs() < 1e-4, "variance {}", variance);
+        }
+    }
+}
@@ -0,0 +1,572 @@
+﻿use crate::nural::float::Float;
+use crate::nural::loss_fns::{expected_class, CROSS_ENTROPY, KL_DIVERGENCE};
+use crate::nural::nural_network::NuralNetworkLossKind;
+use serde::de::value::StrDeserializer;
+use serde::de::{IntoDeserializer, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::ops::Range;
+
+// Implement this to train with a custom loss. Only the built-in losses below can be saved
+// with a network, they are written by name.
+pub trait Loss: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn gradient(&self, actual: &[Float], expected: &[Float]) -> Vec<Float>;
+    fn value(&self, actual: &[Float], expected: &[Float]) -> Float;
+}
+
+impl Loss for NuralNetworkLossKind {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn gradient(&self, actual: &[Float], expected: &[Float]) -> Vec<Float> {
+        (self.loss_fn().dx)(actual, expected)
+    }
+
+    fn value(&self, actual: &[Float], expected: &[Float]) -> Float {
+        (self.loss_fn().fx)(actual, expected)
+    }
+}
+
+// Wraps a classification loss (one-hot expected outputs) with label smoothing and per-class
+// weights, the weight of the expected class scales the loss of the sample.
+#[derive(Deserialize, Serialize)]
+pub struct ClassificationLoss {
+    class_weights: Option<Vec<Float>>,
+    label_smoothing: Float,
+    loss: Box<dyn Loss>,
+}
+
+impl ClassificationLoss {
+    pub fn new(loss: impl Loss + 'static) -> ClassificationLoss {
+        ClassificationLoss {
+            class_weights: None,
+            label_smoothing: 0.0,
+            loss: Box::new(loss),
+        }
+    }
+
+    // Weights every class by `samples / (classes * class samples)` so rare classes count as
+    // much as common ones in total.
+    pub fn with_balanced_class_weights(
+        self,
+        data: &[(Vec<Float>, Vec<Float>)],
+    ) -> ClassificationLoss {
+        let classes = data.first().map_or(0, |(_, expected)| expected.len());
+        let mut class_samples = vec![0usize; classes];
+        for (_, expected) in data.iter() {
+            class_samples[expected_class(expected)] += 1;
+        }
+
+        let class_weights = class_samples
+            .iter()
+            .map(|&samples| {
+                if samples == 0 {
+                    0.0
+                } else {
+                    data.len() as Float / (classes * samples) as Float
+                }
+            })
+            .collect();
+        self.with_class_weights(class_weights)
+    }
+
+    pub fn with_class_weights(mut self, class_weights: Vec<Float>) -> ClassificationLoss {
+        self.class_weights = Some(class_weights);
+        self
+    }
+
+    // Moves `label_smoothing` of the expected probability mass evenly onto all classes.
+    pub fn with_label_smoothing(mut self, label_smoothing: Float) -> ClassificationLoss {
+        assert!(
+            (0.0..1.0).contains(&label_smoothing),
+            "Label smoothing must be in [0, 1)"
+        );
+        self.label_smoothing = label_smoothing;
+        self
+    }
+
+    fn class_weight(&self, expected: &[Float]) -> Float {
+        match &self.class_weights {
+            Some(class_weights) => class_weights[expected_class(expected)],
+            None => 1.0,
+        }
+    }
+
+    fn smooth(&self, expected: &[Float]) -> Vec<Float> {
+        expected
+            .iter()
+            .map(|expected_val| {
+                expected_val * (1.0 - self.label_smoothing)
+                    + self.label_smoothing / expected.len() as Float
+            })
+            .collect()
+    }
+}
+
+impl Loss for ClassificationLoss {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn gradient(&self, actual: &[Float], expected: &[Float]) -> Vec<Float> {
+        let class_weight = self.class_weight(expected);
+        self.loss
+            .gradient(actual, &self.smooth(expected))
+            .iter()
+            .map(|gradient_val| gradient_val * class_weight)
+            .collect()
+    }
+
+    fn value(&self, actual: &[Float], expected: &[Float]) -> Float {
+        self.class_weight(expected) * self.loss.value(actual, &self.smooth(expected))
+    }
+}
+
+// Knowledge distillation on logits: the network is trained on the temperature-softened
+// outputs of a teacher mixed with the hard labels. `NuralNetwork::train_distilled` passes the
+// teacher's soft targets next to the one-hot labels, as a plain `Loss` only the hard term is
+// left. Both networks should end without a SoftmaxLayer, the loss applies the softmax itself.
+#[derive(Deserialize, Serialize)]
+pub struct DistillationLoss {
+    soft_target_weight: Float,
+    temperature: Float,
+}
+
+// Weighted sum of losses, a term can be limited to a range of the output so a single output
+// vector can carry several heads, e.g. 10 digit classes followed by 2 source classes.
+#[derive(Deserialize, Serialize)]
+pub struct WeightedSumLoss {
+    terms: Vec<WeightedLossTerm>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct WeightedLossTerm {
+    loss: Box<dyn Loss>,
+    range: Option<Range<usize>>,
+    weight: Float,
+}
+
+impl DistillationLoss {
+    pub fn new(temperature: Float) -> DistillationLoss {
+        assert!(temperature > 0.0, "Temperature must be positive");
+        DistillationLoss {
+            soft_target_weight: 0.9,
+            temperature,
+        }
+    }
+
+    // Share of the soft target term in the loss, the hard labels get the rest.
+    pub fn with_soft_target_weight(mut self, soft_target_weight: Float) -> DistillationLoss {
+        assert!(
+            (0.0..=1.0).contains(&soft_target_weight),
+            "Soft target weight must be in [0, 1]"
+        );
+        self.soft_target_weight = soft_target_weight;
+        self
+    }
+
+    // The teacher's logits softened by the temperature.
+    pub fn soft_targets(&self, teacher_output: &[Float]) -> Vec<Float> {
+        softmax(teacher_output, self.temperature)
+    }
+
+    // The soft term is scaled by temperature² so its gradient keeps the size of the hard
+    // term's when the temperature changes.
+    pub fn distilled_gradient(
+        &self,
+        actual: &[Float],
+        label: &[Float],
+        soft_targets: &[Float],
+    ) -> Vec<Float> {
+        softmax(actual, self.temperature)
+            .iter()
+            .zip(soft_targets.iter())
+            .zip(self.gradient(actual, label).iter())
+            .map(|((probability, soft_target), hard_gradient_val)| {
+                self.soft_target_weight * self.temperature * (probability - soft_target)
+                    + (1.0 - self.soft_target_weight) * hard_gradient_val
+            })
+            .collect()
+    }
+
+    pub fn distilled_value(
+        &self,
+        actual: &[Float],
+        label: &[Float],
+        soft_targets: &[Float],
+    ) -> Float {
+        let soft_value = (KL_DIVERGENCE.fx)(&softmax(actual, self.temperature), soft_targets);
+        self.soft_target_weight * self.temperature.powi(2) * soft_value
+            + (1.0 - self.soft_target_weight) * self.value(actual, label)
+    }
+}
+
+// The hard term, cross entropy of the softmax against the label.
+impl Loss for DistillationLoss {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn gradient(&self, actual: &[Float], expected: &[Float]) -> Vec<Float> {
+        softmax(actual, 1.0)
+            .iter()
+            .zip(expected.iter())
+            .map(|(probability, expected_val)| probability - expected_val)
+            .collect()
+    }
+
+    fn value(&self, actual: &[Float], expected: &[Float]) -> Float {
+        (CROSS_ENTROPY.fx)(&softmax(actual, 1.0), expected)
+    }
+}
+
+impl WeightedSumLoss {
+    pub fn new() -> WeightedSumLoss {
+        WeightedSumLoss { terms: vec![] }
+    }
+
+    pub fn with_head_loss(
+        mut self,
+        weight: Float,
+        range: Range<usize>,
+        loss: impl Loss + 'static,
+    ) -> WeightedSumLoss {
+        self.terms.push(WeightedLossTerm {
+            loss: Box::new(loss),
+            range: Some(range),
+            weight,
+        });
+        self
+    }
+
+    pub fn with_loss(mut self, weight: Float, loss: impl Loss + 'static) -> WeightedSumLoss {
+        self.terms.push(WeightedLossTerm {
+            loss: Box::new(loss),
+            range: None,
+            weight,
+        });
+        self
+    }
+}
+
+impl WeightedLossTerm {
+    fn range(&self, len: usize) -> Range<usize> {
+        self.range.clone().unwrap_or(0..len)
+    }
+}
+
+impl Loss for WeightedSumLoss {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn gradient(&self, actual: &[Float], expected: &[Float]) -> Vec<Float> {
+        let mut gradient = vec![0.0; actual.len()];
+        for term in self.terms.iter() {
+            let range = term.range(actual.len());
+            let term_gradient = term
+                .loss
+                .gradient(&actual[range.clone()], &expected[range.clone()]);
+            gradient[range]
+                .iter_mut()
+                .zip(term_gradient.iter())
+                .for_each(|(val, term_val)| *val += term.weight * term_val);
+        }
+        gradient
+    }
+
+    fn value(&self, actual: &[Float], expected: &[Float]) -> Float {
+        self.terms
+            .iter()
+            .map(|term| {
+                let range = term.range(actual.len());
+                term.weight * term.loss.value(&actual[range.clone()], &expected[range])
+            })
+            .sum()
+    }
+}
+
+// Built-in kinds keep their plain name so networks saved before the loss trait still load.
+impl Serialize for Box<dyn Loss> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let loss = self.as_any();
+        let loss_type_id = loss.type_id();
+        if loss_type_id == TypeId::of::<NuralNetworkLossKind>() {
+            loss.downcast_ref::<NuralNetworkLossKind>()
+                .unwrap()
+                .serialize(serializer)
+        } else if loss_type_id == TypeId::of::<ClassificationLoss>() {
+            let mut state = serializer.serialize_struct("Loss", 2)?;
+            state.serialize_field("type", "ClassificationLoss")?;
+            state.serialize_field("data", loss.downcast_ref::<ClassificationLoss>().unwrap())?;
+            state.end()
+        } else if loss_type_id == TypeId::of::<DistillationLoss>() {
+            let mut state = serializer.serialize_struct("Loss", 2)?;
+            state.serialize_field("type", "DistillationLoss")?;
+            state.serialize_field("data", loss.downcast_ref::<DistillationLoss>().unwrap())?;
+            state.end()
+        } else if loss_type_id == TypeId::of::<WeightedSumLoss>() {
+            let mut state = serializer.serialize_struct("Loss", 2)?;
+            state.serialize_field("type", "WeightedSumLoss")?;
+            state.serialize_field("data", loss.downcast_ref::<WeightedSumLoss>().unwrap())?;
+            state.end()
+        } else {
+            Err(serde::ser::Error::custom(
+                "Custom Loss types cannot be saved, only the built-in losses",
+            ))
+        }
+    }
+}
+
+impl<'a> Deserialize<'a> for Box<dyn Loss> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        struct LossVisitor;
+
+        impl<'a> Visitor<'a> for LossVisitor {
+            type Value = Box<dyn Loss>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("loss name or serialize_struct::Loss")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Box<dyn Loss>, E>
+            where
+                E: de::Error,
+            {
+                let deserializer: StrDeserializer<E> = value.into_deserializer();
+                NuralNetworkLossKind::deserialize(deserializer)
+                    .map(|l| Box::new(l) as Box<dyn Loss>)
+            }
+
+            fn visit_map<M>(self, mut map: M) -> Result<Box<dyn Loss>, M::Error>
+            where
+                M: MapAccess<'a>,
+            {
+                let loss_type = match map.next_key::<String>()? {
+                    Some(loss_type_key) if loss_type_key == "type" => map.next_value::<String>()?,
+                    _ => return Err(de::Error::missing_field("type")),
+                };
+
+                map.next_key::<String>()?
+                    .ok_or_else(|| de::Error::missing_field("data"))?;
+
+                match loss_type.as_str() {
+                    "ClassificationLoss" => map
+                        .next_value::<ClassificationLoss>()
+                        .map(|l| Box::new(l) as Box<dyn Loss>),
+                    "DistillationLoss" => map
+                        .next_value::<DistillationLoss>()
+                        .map(|l| Box::new(l) as Box<dyn Loss>),
+                    "WeightedSumLoss" => map
+                        .next_value::<WeightedSumLoss>()
+                        .map(|l| Box::new(l) as Box<dyn Loss>),
+                    _ => Err(de::Error::custom("Unknown Loss type")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(LossVisitor)
+    }
+}
+
+// Softmax of the values divided by the temperature, shifted by the maximum for stability.
+fn softmax(values: &[Float], temperature: Float) -> Vec<Float> {
+    let max = values.iter().copied().fold(Float::NEG_INFINITY, Float::max);
+    let exps = values
+        .iter()
+        .map(|val| ((val - max) / temperature).exp())
+        .collect::<Vec<Float>>();
+    let exp_sum = exps.iter().sum::<Float>();
+    exps.iter().map(|exp| exp / exp_sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CustomLoss;
+
+    impl Loss for CustomLoss {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn gradient(&self, actual: &[Float], _expected: &[Float]) -> Vec<Float> {
+            vec![1.0; actual.len()]
+        }
+
+        fn value(&self, actual: &[Float], _expected: &[Float]) -> Float {
+            actual.iter().sum()
+        }
+    }
+
+    #[test]
+    fn label_smoothing_spreads_the_expected_mass() {
+        let loss =
+            ClassificationLoss::new(NuralNetworkLossKind::CrossEntropy).with_label_smoothing(0.2);
+        let actual = [0.1, 0.2, 0.6, 0.1];
+        let smoothed = [0.05, 0.05, 0.85, 0.05];
+        let cross_entropy = NuralNetworkLossKind::CrossEntropy;
+
+        let value = loss.value(&actual, &[0.0, 0.0, 1.0, 0.0]);
+        assert!((value - cross_entropy.value(&actual, &smoothed)).abs() < 1e-12);
+        for (gradient_val, smoothed_gradient_val) in loss
+            .gradient(&actual, &[0.0, 0.0, 1.0, 0.0])
+            .iter()
+            .zip(cross_entropy.gradient(&actual, &smoothed).iter())
+        {
+            assert!((gradient_val - smoothed_gradient_val).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn class_weights_scale_by_the_expected_class() {
+        let loss = ClassificationLoss::new(NuralNetworkLossKind::CrossEntropy)
+            .with_class_weights(vec![1.0, 3.0]);
+        let cross_entropy = NuralNetworkLossKind::CrossEntropy;
+        let actual = [0.3, 0.7];
+        for (expected, class_weight) in [([1.0, 0.0], 1.0), ([0.0, 1.0], 3.0)] {
+            assert_eq!(
+                loss.value(&actual, &expected),
+                class_weight * cross_entropy.value(&actual, &expected)
+            );
+            assert_eq!(
+                loss.gradient(&actual, &expected),
+                cross_entropy
+                    .gradient(&actual, &expected)
+                    .iter()
+                    .map(|gradient_val| class_weight * gradient_val)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn balanced_class_weights_equalize_class_totals() {
+        let data = vec![
+            (vec![], vec![1.0, 0.0, 0.0]),
+            (vec![], vec![1.0, 0.0, 0.0]),
+            (vec![], vec![1.0, 0.0, 0.0]),
+            (vec![], vec![0.0, 1.0, 0.0]),
+        ];
+        let loss = ClassificationLoss::new(NuralNetworkLossKind::CrossEntropy)
+            .with_balanced_class_weights(&data);
+        // Unseen classes get no weight.
+        assert_eq!(loss.class_weights, Some(vec![4.0 / 9.0, 4.0 / 3.0, 0.0]));
+    }
+
+    #[test]
+    fn weighted_sum_weights_every_term() {
+        let loss = WeightedSumLoss::new()
+            .with_loss(0.5, NuralNetworkLossKind::Mse)
+            .with_head_loss(2.0, 1..3, NuralNetworkLossKind::Mae);
+        let actual = [0.3, -0.6, 1.4, 0.2];
+        let expected = [0.0, 0.4, 1.0, 0.5];
+
+        let mse = NuralNetworkLossKind::Mse;
+        let mae = NuralNetworkLossKind::Mae;
+        assert_eq!(
+            loss.value(&actual, &expected),
+            0.5 * mse.value(&actual, &expected) + 2.0 * mae.value(&actual[1..3], &expected[1..3])
+        );
+        let mse_gradient = mse.gradient(&actual, &expected);
+        let mae_gradient = mae.gradient(&actual[1..3], &expected[1..3]);
+        let gradient = loss.gradient(&actual, &expected);
+        for (index, gradient_val) in gradient.iter().enumerate() {
+            let head_gradient_val = if (1..3).contains(&index) {
+                2.0 * mae_gradient[index - 1]
+            } else {
+                0.0
+            };
+            assert_eq!(*gradient_val, 0.5 * mse_gradient[index] + head_gradient_val);
+        }
+    }
+
+    #[test]
+    fn built_in_losses_round_trip() {
+        let actual = [0.2, 0.5, 0.3];
+        let expected = [0.0, 1.0, 0.0];
+        let losses: Vec<Box<dyn Loss>> = vec![
+            Box::new(NuralNetworkLossKind::Huber),
+            Box::new(
+                ClassificationLoss::new(NuralNetworkLossKind::CrossEntropy)
+                    .with_class_weights(vec![1.0, 2.0, 0.5])
+                    .with_label_smoothing(0.1),
+            ),
+            Box::new(DistillationLoss::new(2.0)),
+            Box::new(
+                WeightedSumLoss::new()
+                    .with_loss(0.5, NuralNetworkLossKind::Mse)
+                    .with_head_loss(2.0, 0..2, NuralNetworkLossKind::Mae),
+            ),
+        ];
+        for loss in losses {
+            let serialized_bytes = serde_cbor::to_vec(&loss).unwrap();
+            let loaded_loss = serde_cbor::from_slice::<Box<dyn Loss>>(&serialized_bytes).unwrap();
+            assert_eq!(
+                loaded_loss.value(&actual, &expected),
+                loss.value(&actual, &expected)
+            );
+            assert_eq!(
+                loaded_loss.gradient(&actual, &expected),
+                loss.gradient(&actual, &expected)
+            );
+        }
+    }
+
+    #[test]
+    fn losses_saved_by_name_load() {
+        // Networks saved before the Loss trait stored the loss kind as a plain name.
+        let serialized_bytes = serde_cbor::to_vec(&"Mae").unwrap();
+        let loss = serde_cbor::from_slice::<Box<dyn Loss>>(&serialized_bytes).unwrap();
+        assert!(loss.as_any().is::<NuralNetworkLossKind>());
+        assert_eq!(
+            loss.value(&[0.5, -1.0], &[0.0, 1.0]),
+            NuralNetworkLossKind::Mae.value(&[0.5, -1.0], &[0.0, 1.0])
+        );
+
+        let loss: Box<dyn Loss> = Box::new(NuralNetworkLossKind::Mae);
+        assert_eq!(serde_cbor::to_vec(&loss).unwrap(), serialized_bytes);
+    }
+
+    #[test]
+    fn custom_losses_cannot_be_saved() {
+        let loss: Box<dyn Loss> = Box::new(CustomLoss);
+        assert!(serde_cbor::to_vec(&loss).is_err());
+    }
+
+    #[test]
+    fn distilled_gradient_matches_finite_differences() {
+        let loss = DistillationLoss::new(3.0).with_soft_target_weight(0.7);
+        let actual = [0.4, -1.2, 2.0, 0.1];
+        let label = [0.0, 0.0, 1.0, 0.0];
+        let soft_targets = loss.soft_targets(&[0.9, -0.3, 1.5, 0.2]);
+        let step = Float::EPSILON.cbrt();
+
+        let gradient = loss.distilled_gradient(&actual, &label, &soft_targets);
+        for (index, gradient_val) in gradient.iter().enumerate() {
+            let mut shifted = actual;
+            shifted[index] += step;
+            let plus = loss.distilled_value(&shifted, &label, &soft_targets);
+            shifted[index] -= 2.0 * step;
+            let minus = loss.distilled_value(&shifted, &label, &soft_targets);
+            let numeric = (plus - minus) / (2.0 * step);
+            assert!(
+                (gradient_val - numeric).abs() < 1e-4,
+                "{} vs {}",
+                gradient_val,
+                numeric
+            );
+        }
+    }
+}
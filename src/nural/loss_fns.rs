@@ -1,6 +1,12 @@
-﻿pub struct LossFn<'a> {
-    pub dx: &'a dyn Fn(&[f64], &[f64]) -> Vec<f64>,
-    pub fx: &'a dyn Fn(&[f64], &[f64]) -> f64,
+﻿use crate::nural::float::Float;
+
+// Loss functions of `(actual, expected)`, the gradient is with respect to `actual`.
+pub type LossDx = dyn Fn(&[Float], &[Float]) -> Vec<Float>;
+pub type LossFx = dyn Fn(&[Float], &[Float]) -> Float;
+
+pub struct LossFn<'a> {
+    pub dx: &'a LossDx,
+    pub fx: &'a LossFx,
 }
 
 pub const BINARY_CROSS_ENTROPY: LossFn = LossFn {
@@ -10,7 +16,7 @@ pub const BINARY_CROSS_ENTROPY: LossFn = LossFn {
             .zip(expected.iter())
             .map(|(actual_val, expected_val)| {
                 ((1.0 - actual_val) / (1.0 - expected_val) - actual_val / expected_val)
-                    / actual.len() as f64
+                    / actual.len() as Float
             })
             .collect()
     },
@@ -23,7 +29,7 @@ pub const BINARY_CROSS_ENTROPY: LossFn = LossFn {
                     val - actual_val * expected_val.log(10.0)
                         - (1.0 - actual_val) * (1.0 - expected_val).log(10.0)
                 });
-        sum / (actual.len() as f64)
+        sum / (actual.len() as Float)
     },
 };
 
@@ -33,7 +39,7 @@ pub const MSE: LossFn = LossFn {
             .iter()
             .zip(expected.iter())
             .map(|(actual_val, expected_val)| {
-                (actual_val - expected_val) * 2.0 / actual.len() as f64
+                (actual_val - expected_val) * 2.0 / actual.len() as Float
             })
             .collect()
     },
@@ -44,6 +50,256 @@ pub const MSE: LossFn = LossFn {
             .fold(0.0, |val, (&actual_val, &expected_val)| {
                 val + (expected_val - actual_val).powi(2)
             })
-            / (actual.len() as f64)
+            / (actual.len() as Float)
+    },
+};
+
+const EPSILON: Float = 1e-12;
+const FOCAL_GAMMA: i32 = 2;
+const HUBER_DELTA: Float = 1.0;
+
+// Categorical cross entropy on predicted probabilities, e.g. the output of a SoftmaxLayer.
+pub const CROSS_ENTROPY: LossFn = LossFn {
+    dx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(&actual_val, &expected_val)| -expected_val / actual_val.max(EPSILON))
+            .collect()
+    },
+    fx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .fold(0.0, |val, (&actual_val, &expected_val)| {
+                val - expected_val * actual_val.max(EPSILON).ln()
+            })
+    },
+};
+
+// Multi-class focal loss on predicted probabilities, it scales cross entropy down for
+// classes that are already predicted well so training focuses on the hard samples. The
+// focusing parameter gamma is fixed at 2 and there is no alpha term, wrap the loss in a
+// `ClassificationLoss` with class weights to balance the classes.
+pub const FOCAL: LossFn = LossFn {
+    dx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(&actual_val, &expected_val)| {
+                let actual_val = actual_val.clamp(EPSILON, 1.0);
+                expected_val
+                    * (FOCAL_GAMMA as Float
+                        * (1.0 - actual_val).powi(FOCAL_GAMMA - 1)
+                        * actual_val.ln()
+                        - (1.0 - actual_val).powi(FOCAL_GAMMA) / actual_val)
+            })
+            .collect()
+    },
+    fx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .fold(0.0, |val, (&actual_val, &expected_val)| {
+                let actual_val = actual_val.clamp(EPSILON, 1.0);
+                val - expected_val * (1.0 - actual_val).powi(FOCAL_GAMMA) * actual_val.ln()
+            })
     },
 };
+
+// Multi-class (Weston-Watkins) hinge loss, the expected output is one-hot and every other
+// class is pushed at least a margin of 1 below the expected class.
+pub const HINGE: LossFn = LossFn {
+    dx: &|actual, expected| {
+        let margins = hinge_margins(actual, expected);
+        hinge_gradient(
+            &margins,
+            expected,
+            |margin| {
+                if margin > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            },
+        )
+    },
+    fx: &|actual, expected| hinge_margins(actual, expected).iter().sum(),
+};
+
+// Quadratic for errors up to a delta of 1 and linear beyond, the delta is fixed.
+pub const HUBER: LossFn = LossFn {
+    dx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(actual_val, expected_val)| {
+                (actual_val - expected_val).clamp(-HUBER_DELTA, HUBER_DELTA) / actual.len() as Float
+            })
+            .collect()
+    },
+    fx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .fold(0.0, |val, (&actual_val, &expected_val)| {
+                let error = (actual_val - expected_val).abs();
+                if error <= HUBER_DELTA {
+                    val + 0.5 * error.powi(2)
+                } else {
+                    val + HUBER_DELTA * (error - 0.5 * HUBER_DELTA)
+                }
+            })
+            / (actual.len() as Float)
+    },
+};
+
+// KL(expected || actual), both are expected to be probability distributions.
+pub const KL_DIVERGENCE: LossFn = LossFn {
+    dx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(&actual_val, &expected_val)| -expected_val / actual_val.max(EPSILON))
+            .collect()
+    },
+    fx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .filter(|(_, &expected_val)| expected_val > 0.0)
+            .fold(0.0, |val, (&actual_val, &expected_val)| {
+                val + expected_val * (expected_val / actual_val.max(EPSILON)).ln()
+            })
+    },
+};
+
+pub const MAE: LossFn = LossFn {
+    dx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .map(|(actual_val, expected_val)| {
+                (actual_val - expected_val).signum() / actual.len() as Float
+            })
+            .collect()
+    },
+    fx: &|actual, expected| {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .fold(0.0, |val, (&actual_val, &expected_val)| {
+                val + (actual_val - expected_val).abs()
+            })
+            / (actual.len() as Float)
+    },
+};
+
+pub const SQUARED_HINGE: LossFn = LossFn {
+    dx: &|actual, expected| {
+        let margins = hinge_margins(actual, expected);
+        hinge_gradient(&margins, expected, |margin| 2.0 * margin)
+    },
+    fx: &|actual, expected| {
+        hinge_margins(actual, expected)
+            .iter()
+            .map(|margin| margin.powi(2))
+            .sum()
+    },
+};
+
+// Positive part of `1 + actual[j] - actual[expected class]` for every other class j.
+fn hinge_margins(actual: &[Float], expected: &[Float]) -> Vec<Float> {
+    let expected_class = expected_class(expected);
+    actual
+        .iter()
+        .enumerate()
+        .map(|(class, actual_val)| {
+            if class == expected_class {
+                0.0
+            } else {
+                (1.0 + actual_val - actual[expected_class]).max(0.0)
+            }
+        })
+        .collect()
+}
+
+fn hinge_gradient(
+    margins: &[Float],
+    expected: &[Float],
+    margin_dx: impl Fn(Float) -> Float,
+) -> Vec<Float> {
+    let mut gradient = margins
+        .iter()
+        .map(|&margin| margin_dx(margin))
+        .collect::<Vec<Float>>();
+    gradient[expected_class(expected)] = -gradient.iter().sum::<Float>();
+    gradient
+}
+
+pub fn expected_class(expected: &[Float]) -> usize {
+    expected
+        .iter()
+        .enumerate()
+        .fold(0, |max_class, (class, &val)| {
+            if val > expected[max_class] {
+                class
+            } else {
+                max_class
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::{relative_error, GradientCheck};
+
+    // The test points keep away from the kinks of the piecewise losses.
+    fn assert_gradient_matches(loss_fn: LossFn, actual: &[Float], expected: &[Float]) {
+        let step = GradientCheck::step();
+        let gradient = (loss_fn.dx)(actual, expected);
+        let mut shifted = actual.to_vec();
+        for index in 0..actual.len() {
+            shifted[index] = actual[index] + step;
+            let loss_up = (loss_fn.fx)(&shifted, expected);
+            shifted[index] = actual[index] - step;
+            let loss_down = (loss_fn.fx)(&shifted, expected);
+            shifted[index] = actual[index];
+
+            let numeric_gradient = (loss_up - loss_down) / (2.0 * step);
+            assert!(
+                relative_error(gradient[index], numeric_gradient) <= GradientCheck::tolerance(),
+                "output {}: {} vs {}",
+                index,
+                gradient[index],
+                numeric_gradient
+            );
+        }
+    }
+
+    #[test]
+    fn regression_gradients_match_finite_differences() {
+        let actual = [0.3, 2.5, -1.7, 0.05];
+        let expected = [0.1, 0.0, 0.2, 0.4];
+        assert_gradient_matches(HUBER, &actual, &expected);
+        assert_gradient_matches(MAE, &actual, &expected);
+        assert_gradient_matches(MSE, &actual, &expected);
+    }
+
+    #[test]
+    fn hinge_gradients_match_finite_differences() {
+        let actual = [0.2, 0.9, 0.5, -0.8];
+        let expected = [0.0, 1.0, 0.0, 0.0];
+        assert_gradient_matches(HINGE, &actual, &expected);
+        assert_gradient_matches(SQUARED_HINGE, &actual, &expected);
+    }
+
+    #[test]
+    fn probability_gradients_match_finite_differences() {
+        let actual = [0.2, 0.5, 0.3];
+        assert_gradient_matches(CROSS_ENTROPY, &actual, &[0.0, 1.0, 0.0]);
+        assert_gradient_matches(FOCAL, &actual, &[0.0, 1.0, 0.0]);
+        assert_gradient_matches(KL_DIVERGENCE, &actual, &[0.1, 0.6, 0.3]);
+    }
+}
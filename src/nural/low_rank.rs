@@ -0,0 +1,266 @@
+﻿use crate::nural::dense_layer::DenseLayer;
+use crate::nural::float::Float;
+use crate::nural::nural_network::NuralNetwork;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use ndarray::{s, Array1, Array2, Axis};
+
+// Compresses a dense layer by replacing its weights W with the truncated SVD W ≈ U Σ Vᵀ, split
+// into two dense layers: the first projects the inputs onto the top right singular vectors,
+// the second maps them to the outputs and keeps the bias. The pair has
+// rank × (inputs + outputs) weights instead of inputs × outputs.
+pub struct LowRankFactorization {
+    fine_tune_epochs: usize,
+    target: LowRankTarget,
+}
+
+pub enum LowRankTarget {
+    // Smallest rank keeping this share of the squared singular values.
+    Energy(Float),
+    Rank(usize),
+}
+
+pub struct LowRankReport {
+    // Change from the accuracy of the network before factorization.
+    pub accuracy_delta: Float,
+    pub accuracy: Float,
+    pub energy: Float,
+    pub factorized_parameter_count: usize,
+    pub original_parameter_count: usize,
+    pub rank: usize,
+}
+
+impl LowRankFactorization {
+    pub fn new(target: LowRankTarget) -> LowRankFactorization {
+        LowRankFactorization {
+            fine_tune_epochs: 0,
+            target,
+        }
+    }
+
+    pub fn with_fine_tune_epochs(mut self, fine_tune_epochs: usize) -> LowRankFactorization {
+        self.fine_tune_epochs = fine_tune_epochs;
+        self
+    }
+
+    // Replaces the dense layer at the index with its two factors, fine-tunes on the training
+    // data and measures the accuracy on the test data.
+    pub fn factorize(
+        &self,
+        network: &mut NuralNetwork,
+        layer_index: usize,
+        training_data: &[(Vec<Float>, Vec<Float>)],
+        test_data: &[(Vec<Float>, Vec<Float>)],
+    ) -> LowRankReport {
+        let initial_accuracy = network.accuracy(test_data);
+        let dense_layer = network.layers()[layer_index]
+            .as_any()
+            .downcast_ref::<DenseLayer>()
+            .expect("Only dense layers can be factorized");
+        let original_parameter_count = dense_layer.parameter_count();
+
+        let (first_layer, second_layer, energy) = self.factorize_layer(dense_layer);
+        let rank = first_layer.weights().nrows();
+        let factorized_parameter_count =
+            first_layer.parameter_count() + second_layer.parameter_count();
+        network.replace_layer(
+            layer_index,
+            vec![Box::new(first_layer), Box::new(second_layer)],
+        );
+
+        if self.fine_tune_epochs > 0 {
+            network.train(training_data, self.fine_tune_epochs);
+        }
+
+        let accuracy = network.accuracy(test_data);
+        LowRankReport {
+            accuracy_delta: accuracy - initial_accuracy,
+            accuracy,
+            energy,
+            factorized_parameter_count,
+            original_parameter_count,
+            rank,
+        }
+    }
+
+    // The two layers replacing the given one and the share of the energy they keep.
+    pub fn factorize_layer(&self, dense_layer: &DenseLayer) -> (DenseLayer, DenseLayer, Float) {
+        let weights = dense_layer.weights();
+        let bias = dense_layer.parameters()[0]
+            .iter()
+            .copied()
+            .collect::<Vec<Float>>();
+
+        // The singular vectors of the shorter side come from the eigenvectors of the smaller
+        // Gram matrix, the other side follows by multiplying with the weights.
+        let transposed = weights.nrows() > weights.ncols();
+        let gram = if transposed {
+            weights.t().dot(weights)
+        } else {
+            weights.dot(&weights.t())
+        };
+        let (eigenvalues, eigenvectors) = symmetric_eigen(gram);
+
+        let total_energy = eigenvalues.sum();
+        let rank = match self.target {
+            LowRankTarget::Energy(energy) => {
+                let mut kept_energy = 0.0;
+                eigenvalues
+                    .iter()
+                    .position(|eigenvalue| {
+                        kept_energy += eigenvalue;
+                        kept_energy >= energy * total_energy
+                    })
+                    .map_or(eigenvalues.len(), |position| position + 1)
+            }
+            LowRankTarget::Rank(rank) => rank.clamp(1, eigenvalues.len()),
+        };
+        let energy = if total_energy > 0.0 {
+            eigenvalues.slice(s![..rank]).sum() / total_energy
+        } else {
+            1.0
+        };
+
+        let singular_vectors = eigenvectors.slice(s![.., ..rank]).to_owned();
+        let (first_weights, second_weights) = if transposed {
+            // Columns of V: Vₖᵀ, then W Vₖ = Uₖ Σₖ.
+            (
+                singular_vectors.t().to_owned(),
+                weights.dot(&singular_vectors),
+            )
+        } else {
+            // Columns of U: Uₖᵀ W = Σₖ Vₖᵀ, then Uₖ.
+            (singular_vectors.t().dot(weights), singular_vectors)
+        };
+
+        (
+            DenseLayer::from_weights(first_weights, vec![0.0; rank]),
+            DenseLayer::from_weights(second_weights, bias),
+            energy,
+        )
+    }
+}
+
+// Cyclic Jacobi eigendecomposition of a symmetric matrix. Returns the eigenvalues in
+// descending order, clamped at zero, and the matching eigenvectors as columns.
+fn symmetric_eigen(mut matrix: Array2<Float>) -> (Array1<Float>, Array2<Float>) {
+    const MAX_SWEEPS: usize = 100;
+
+    let size = matrix.nrows();
+    let mut eigenvectors = Array2::<Float>::eye(size);
+    let tolerance = Float::EPSILON * matrix.iter().map(|val| val * val).sum::<Float>().sqrt();
+
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal = matrix
+            .indexed_iter()
+            .filter(|((row, column), _)| row != column)
+            .map(|(_, val)| val * val)
+            .sum::<Float>()
+            .sqrt();
+        if off_diagonal <= tolerance {
+            break;
+        }
+
+        for p in 0..size {
+            for q in p + 1..size {
+                if matrix[[p, q]].abs() <= Float::MIN_POSITIVE {
+                    continue;
+                }
+                // Rotation in the (p, q) plane that zeroes matrix[p, q].
+                let theta = (matrix[[q, q]] - matrix[[p, p]]) / (2.0 * matrix[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                rotate(&mut matrix, Axis(1), p, q, c, s);
+                rotate(&mut matrix, Axis(0), p, q, c, s);
+                rotate(&mut eigenvectors, Axis(1), p, q, c, s);
+            }
+        }
+    }
+
+    let mut order = (0..size).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| matrix[[b, b]].total_cmp(&matrix[[a, a]]));
+    let eigenvalues = order
+        .iter()
+        .map(|&index| matrix[[index, index]].max(0.0))
+        .collect();
+    let eigenvectors = eigenvectors.select(Axis(1), &order);
+    (eigenvalues, eigenvectors)
+}
+
+fn rotate(matrix: &mut Array2<Float>, axis: Axis, p: usize, q: usize, c: Float, s: Float) {
+    let p_lane = matrix.index_axis(axis, p).to_owned();
+    let q_lane = matrix.index_axis(axis, q).to_owned();
+    matrix
+        .index_axis_mut(axis, p)
+        .assign(&(&p_lane * c - &q_lane * s));
+    matrix
+        .index_axis_mut(axis, q)
+        .assign(&(&p_lane * s + &q_lane * c));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dense_layer(outputs: usize, inputs: usize) -> DenseLayer {
+        let weights = Array2::from_shape_fn((outputs, inputs), |(row, column)| {
+            ((row * inputs + column) as Float * 0.37).sin()
+        });
+        let bias = (0..outputs).map(|output| output as Float * 0.1).collect();
+        DenseLayer::from_weights(weights, bias)
+    }
+
+    fn assert_full_rank_reproduces_outputs(outputs: usize, inputs: usize) {
+        let layer = dense_layer(outputs, inputs);
+        let (first_layer, second_layer, energy) =
+            LowRankFactorization::new(LowRankTarget::Rank(outputs.min(inputs)))
+                .factorize_layer(&layer);
+        assert!((energy - 1.0).abs() < 1e-12, "energy {}", energy);
+
+        let input = (0..inputs)
+            .map(|index| (index as Float * 0.37).cos())
+            .collect::<Vec<Float>>();
+        let factorized_output = second_layer.forward(&first_layer.forward(&input));
+        for (val, factorized_val) in layer.forward(&input).iter().zip(factorized_output) {
+            assert!(
+                (val - factorized_val).abs() < 1e-12,
+                "output {}, factorized {}",
+                val,
+                factorized_val
+            );
+        }
+    }
+
+    #[test]
+    fn full_rank_of_a_wide_layer_reproduces_its_outputs() {
+        assert_full_rank_reproduces_outputs(3, 5);
+    }
+
+    #[test]
+    fn full_rank_of_a_tall_layer_reproduces_its_outputs() {
+        assert_full_rank_reproduces_outputs(5, 3);
+    }
+
+    #[test]
+    fn energy_target_picks_the_smallest_rank_reaching_it() {
+        // Singular values 3, 2 and 1: the top ranks keep 9/14, 13/14 and all of the energy.
+        let mut weights = Array2::zeros((3, 4));
+        weights[[0, 1]] = 2.0;
+        weights[[1, 3]] = -1.0;
+        weights[[2, 0]] = 3.0;
+        let layer = DenseLayer::from_weights(weights, vec![0.0; 3]);
+        let rank_and_energy = |energy| {
+            let (first_layer, _, energy) =
+                LowRankFactorization::new(LowRankTarget::Energy(energy)).factorize_layer(&layer);
+            (first_layer.weights().nrows(), energy)
+        };
+
+        assert_eq!(rank_and_energy(0.5).0, 1);
+        assert_eq!(rank_and_energy(9.0 / 14.0).0, 1);
+        let (rank, energy) = rank_and_energy(0.9);
+        assert_eq!(rank, 2);
+        assert!((energy - 13.0 / 14.0).abs() < 1e-12, "energy {}", energy);
+        assert_eq!(rank_and_energy(0.95).0, 3);
+    }
+}
@@ -1,7 +1,37 @@
 ﻿pub mod activation_fns;
 pub mod activation_layer;
+pub mod array_serde;
+pub mod attention_layer;
+pub mod concat_layer;
+pub mod conv_2d_layer;
+pub mod conv_transpose_2d_layer;
 pub mod dense_layer;
+pub mod embedding_layer;
+pub mod flatten_layer;
+pub mod float;
+pub mod gradient_check;
+pub mod gradient_clipping;
+pub mod gru_layer;
+pub mod layer_norm_layer;
+pub mod loss;
 pub mod loss_fns;
+pub mod low_rank;
+pub mod lstm_layer;
+pub mod net2net;
+pub mod nural_graph;
 pub mod nural_network;
 pub mod nural_network_layer;
+pub mod nural_network_statistics;
+pub mod nural_network_summary;
+pub mod nural_network_training;
+pub mod parameter_gradient;
+pub mod positional_encoding_layer;
+pub mod pruning;
+pub mod quantized_network;
+pub mod recurrent;
+pub mod regularization;
+pub mod reshape_layer;
+pub mod rnn_layer;
 pub mod softmax_layer;
+pub mod transformer_encoder_layer;
+pub mod upsample_layer;
@@ -0,0 +1,184 @@
+﻿use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::dense_layer::DenseLayer;
+use crate::nural::float::Float;
+use crate::nural::nural_network::NuralNetwork;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use ndarray::{Array2, Axis};
+use rand::Rng;
+
+// Function-preserving growth of trained networks (Net2Net): the grown network computes the
+// same outputs as before, so training continues from where it was instead of restarting.
+impl NuralNetwork {
+    // Inserts an identity dense layer and a ReLU activation after the ReLU activation that
+    // follows the dense layer at the index. ReLU is idempotent, so the new pair passes the
+    // activations through unchanged, with other activations it would not.
+    pub fn deepen(&mut self, layer_index: usize) -> Result<(), String> {
+        let width = dense_layer(self.layer(layer_index)?, layer_index)?
+            .weights()
+            .nrows();
+        let activation_index = layer_index + 1;
+        let is_relu = self
+            .layer(activation_index)
+            .ok()
+            .and_then(|layer| layer.as_any().downcast_ref::<ActivationLayer>())
+            .is_some_and(|layer| matches!(layer.kind(), ActivationLayerKind::ReLu));
+        if !is_relu {
+            return Err(format!(
+                "layer {}: deepen needs a ReLu activation after the dense layer",
+                layer_index
+            ));
+        }
+
+        self.insert_layer(
+            activation_index + 1,
+            Box::new(DenseLayer::from_weights(
+                Array2::eye(width),
+                vec![0.0; width],
+            )),
+        );
+        self.insert_layer(
+            activation_index + 2,
+            Box::new(ActivationLayer::new(ActivationLayerKind::ReLu)),
+        );
+        Ok(())
+    }
+
+    // Grows the dense layer at the index to `width` outputs. Every new unit copies a random
+    // existing unit and the next dense layer splits the outgoing weights of a copied unit
+    // between its copies. Only activation layers may sit between the two dense layers. The
+    // split is slightly uneven so the copies do not keep getting the same updates.
+    pub fn widen(&mut self, layer_index: usize, width: usize) -> Result<(), String> {
+        let (weights, bias) = parameters(dense_layer(self.layer(layer_index)?, layer_index)?);
+        let next_index = (layer_index + 1..self.layers().len())
+            .find(|&index| !self.layers()[index].as_any().is::<ActivationLayer>())
+            .ok_or(format!("layer {}: no dense layer follows", layer_index))?;
+        let (next_weights, next_bias) =
+            parameters(dense_layer(self.layer(next_index)?, next_index)?);
+        if width < weights.nrows() {
+            return Err(format!(
+                "layer {}: cannot widen {} outputs to {}",
+                layer_index,
+                weights.nrows(),
+                width
+            ));
+        }
+
+        let mut rng = rand::rng();
+        let units = (0..width)
+            .map(|unit| {
+                if unit < weights.nrows() {
+                    unit
+                } else {
+                    rng.random_range(0..weights.nrows())
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut copies = vec![0; weights.nrows()];
+        units.iter().for_each(|&unit| copies[unit] += 1);
+
+        // Shares of the outgoing weights, they sum to one over the copies of a unit.
+        let mut shares = units
+            .iter()
+            .map(|&unit| (1.0 + rng.random_range(-0.01..0.01)) / copies[unit] as Float)
+            .collect::<Vec<Float>>();
+        let mut share_sums = vec![0.0; weights.nrows()];
+        units
+            .iter()
+            .zip(shares.iter())
+            .for_each(|(&unit, share)| share_sums[unit] += share);
+        shares
+            .iter_mut()
+            .zip(units.iter())
+            .for_each(|(share, &unit)| *share /= share_sums[unit]);
+
+        let widened_weights = weights.select(Axis(0), &units);
+        let widened_bias = units.iter().map(|&unit| bias[unit]).collect();
+        let mut widened_next_weights = next_weights.select(Axis(1), &units);
+        widened_next_weights
+            .columns_mut()
+            .into_iter()
+            .zip(shares.iter())
+            .for_each(|(mut column, share)| column *= *share);
+
+        dense_layer_mut(self, layer_index).set_weights(widened_weights, widened_bias);
+        dense_layer_mut(self, next_index).set_weights(widened_next_weights, next_bias);
+        Ok(())
+    }
+
+    fn layer(&self, layer_index: usize) -> Result<&dyn NuralNetworkLayer, String> {
+        self.layers()
+            .get(layer_index)
+            .map(|layer| layer.as_ref())
+            .ok_or(format!(
+                "layer {}: out of range for {} layers",
+                layer_index,
+                self.layers().len()
+            ))
+    }
+}
+
+fn dense_layer(layer: &dyn NuralNetworkLayer, layer_index: usize) -> Result<&DenseLayer, String> {
+    layer
+        .as_any()
+        .downcast_ref::<DenseLayer>()
+        .ok_or(format!("layer {}: not a DenseLayer", layer_index))
+}
+
+fn dense_layer_mut(network: &mut NuralNetwork, layer_index: usize) -> &mut DenseLayer {
+    network.layers_mut()[layer_index]
+        .as_any_mut()
+        .downcast_mut::<DenseLayer>()
+        .unwrap()
+}
+
+fn parameters(dense_layer: &DenseLayer) -> (Array2<Float>, Vec<Float>) {
+    let bias = dense_layer.parameters()[0].iter().copied().collect();
+    (dense_layer.weights().clone(), bias)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    fn network(activation: ActivationLayerKind) -> NuralNetwork {
+        NuralNetwork::new(
+            vec![
+                Box::new(DenseLayer::new(4, 6)),
+                Box::new(ActivationLayer::new(activation)),
+                Box::new(DenseLayer::new(6, 3)),
+            ],
+            0.01,
+            NuralNetworkLossKind::Mse,
+        )
+    }
+
+    fn assert_same_outputs(before: &[Float], after: &[Float]) {
+        before
+            .iter()
+            .zip(after.iter())
+            .for_each(|(before, after)| assert!((before - after).abs() < 1e-9));
+    }
+
+    #[test]
+    fn deepen_and_widen_preserve_outputs() {
+        let input = [0.3, -0.7, 0.1, 0.9];
+        let mut nural_network = network(ActivationLayerKind::ReLu);
+        let before = nural_network.predict(&input);
+
+        nural_network.deepen(0).unwrap();
+        assert_eq!(nural_network.layers().len(), 5);
+        assert_same_outputs(&before, &nural_network.predict(&input));
+
+        nural_network.widen(0, 9).unwrap();
+        assert_same_outputs(&before, &nural_network.predict(&input));
+    }
+
+    #[test]
+    fn deepen_rejects_other_activations_and_missing_layers() {
+        assert!(network(ActivationLayerKind::Tanh).deepen(0).is_err());
+        assert!(network(ActivationLayerKind::ReLu).deepen(2).is_err());
+        assert!(network(ActivationLayerKind::ReLu).deepen(7).is_err());
+        assert!(network(ActivationLayerKind::ReLu).widen(7, 9).is_err());
+    }
+}
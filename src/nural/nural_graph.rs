@@ -0,0 +1,403 @@
+﻿use crate::nural::float::Float;
+use crate::nural::loss::Loss;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use serde::{Deserialize, Serialize};
+
+// A network whose layers form a directed acyclic graph instead of a single stack, nodes
+// refer to their inputs by index so branches, residual additions and multiple inputs
+// and outputs can be described and serialized. Training is plain per-sample gradient
+// descent, every layer is updated through `NuralNetworkLayer::backward` as soon as its
+// gradient is known. Gradient clipping, batches and frozen layers of `NuralNetwork` are
+// not applied to graphs.
+#[derive(Deserialize, Serialize)]
+pub struct NuralGraph {
+    inputs: Vec<usize>,
+    learning_rate: Float,
+    #[serde(alias = "loss_kind")]
+    loss: Box<dyn Loss>,
+    nodes: Vec<NuralGraphNode>,
+    outputs: Vec<usize>,
+}
+
+// Training samples hold one vector per graph input and one expected vector per output.
+pub type NuralGraphSample = (Vec<Vec<Float>>, Vec<Vec<Float>>);
+
+#[derive(Deserialize, Serialize)]
+pub struct NuralGraphNode {
+    inputs: Vec<usize>,
+    kind: NuralGraphNodeKind,
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum NuralGraphNodeKind {
+    Add,
+    Concat,
+    Input(usize),
+    Layer(Box<dyn NuralNetworkLayer>),
+}
+
+impl NuralGraph {
+    pub fn new(learning_rate: Float, loss: impl Loss + 'static) -> Self {
+        NuralGraph {
+            inputs: vec![],
+            learning_rate,
+            loss: Box::new(loss),
+            nodes: vec![],
+            outputs: vec![],
+        }
+    }
+
+    pub fn add(&mut self, inputs: &[usize]) -> usize {
+        assert!(!inputs.is_empty(), "Add needs at least one input");
+        self.push_node(inputs.to_vec(), NuralGraphNodeKind::Add)
+    }
+
+    pub fn concat(&mut self, inputs: &[usize]) -> usize {
+        assert!(!inputs.is_empty(), "Concat needs at least one input");
+        self.push_node(inputs.to_vec(), NuralGraphNodeKind::Concat)
+    }
+
+    pub fn input(&mut self, size: usize) -> usize {
+        let node = self.push_node(vec![], NuralGraphNodeKind::Input(size));
+        self.inputs.push(node);
+        node
+    }
+
+    pub fn layer(&mut self, input: usize, layer: Box<dyn NuralNetworkLayer>) -> usize {
+        self.push_node(vec![input], NuralGraphNodeKind::Layer(layer))
+    }
+
+    pub fn output(&mut self, node: usize) {
+        assert!(node < self.nodes.len(), "Unknown graph node {}", node);
+        self.outputs.push(node);
+    }
+
+    pub fn load_file(file_path: &str) -> Result<NuralGraph, std::io::Error> {
+        let serialized_bytes = std::fs::read(file_path)?;
+        serde_cbor::from_slice(&serialized_bytes).map_err(std::io::Error::other)
+    }
+
+    pub fn predict(&self, inputs: &[&[Float]]) -> Vec<Vec<Float>> {
+        let values = self.forward(inputs);
+        self.outputs
+            .iter()
+            .map(|&output| values[output].clone())
+            .collect()
+    }
+
+    pub fn save_file(&self, file_path: &str) -> Result<(), std::io::Error> {
+        let serialized_bytes = serde_cbor::to_vec(self).map_err(std::io::Error::other)?;
+        std::fs::write(file_path, serialized_bytes)
+    }
+
+    pub fn train(&mut self, data: &[NuralGraphSample], epochs: usize) {
+        let order = self.order();
+
+        for epoch in 0..epochs {
+            let mut error = 0.0;
+
+            for (inputs, expected_outputs) in data.iter() {
+                let inputs = inputs
+                    .iter()
+                    .map(|input| input.as_slice())
+                    .collect::<Vec<_>>();
+                let values = self.forward_ordered(&order, &inputs);
+
+                let mut gradients: Vec<Option<Vec<Float>>> = vec![None; self.nodes.len()];
+                for (&output, expected_output) in self.outputs.iter().zip(expected_outputs.iter()) {
+                    error += self.loss.value(&values[output], expected_output);
+                    let gradient = self.loss.gradient(&values[output], expected_output);
+                    accumulate(&mut gradients[output], &gradient);
+                }
+
+                self.backward(&order, &values, gradients);
+            }
+
+            println!(
+                "epoch {}/{} error: {}",
+                epoch + 1,
+                epochs,
+                error / data.len() as Float + self.penalty()
+            );
+        }
+    }
+
+    // Updates the layers directly with `NuralNetworkLayer::backward`, see the type comment.
+    fn backward(
+        &mut self,
+        order: &[usize],
+        values: &[Vec<Float>],
+        mut gradients: Vec<Option<Vec<Float>>>,
+    ) {
+        for &node_index in order.iter().rev() {
+            let gradient = match gradients[node_index].take() {
+                Some(gradient) => gradient,
+                None => continue,
+            };
+
+            let node = &mut self.nodes[node_index];
+            match &mut node.kind {
+                NuralGraphNodeKind::Add => {
+                    for &input in node.inputs.iter() {
+                        accumulate(&mut gradients[input], &gradient);
+                    }
+                }
+                NuralGraphNodeKind::Concat => {
+                    let mut offset = 0;
+                    for &input in node.inputs.iter() {
+                        let size = values[input].len();
+                        accumulate(&mut gradients[input], &gradient[offset..offset + size]);
+                        offset += size;
+                    }
+                }
+                NuralGraphNodeKind::Input(_) => {}
+                NuralGraphNodeKind::Layer(layer) => {
+                    let input = node.inputs[0];
+                    let input_gradient = layer.backward(
+                        &values[input],
+                        &values[node_index],
+                        &gradient,
+                        self.learning_rate,
+                    );
+                    accumulate(&mut gradients[input], &input_gradient);
+                }
+            }
+        }
+    }
+
+    fn forward(&self, inputs: &[&[Float]]) -> Vec<Vec<Float>> {
+        self.forward_ordered(&self.order(), inputs)
+    }
+
+    fn forward_ordered(&self, order: &[usize], inputs: &[&[Float]]) -> Vec<Vec<Float>> {
+        assert_eq!(
+            inputs.len(),
+            self.inputs.len(),
+            "Graph input count mismatch"
+        );
+
+        let mut values = vec![vec![]; self.nodes.len()];
+        for &node_index in order.iter() {
+            let node = &self.nodes[node_index];
+            values[node_index] = match &node.kind {
+                NuralGraphNodeKind::Add => {
+                    let mut sum = values[node.inputs[0]].clone();
+                    for &input in node.inputs[1..].iter() {
+                        assert_eq!(
+                            sum.len(),
+                            values[input].len(),
+                            "Add inputs must have equal sizes"
+                        );
+                        sum.iter_mut()
+                            .zip(values[input].iter())
+                            .for_each(|(sum_val, val)| *sum_val += val);
+                    }
+                    sum
+                }
+                NuralGraphNodeKind::Concat => node
+                    .inputs
+                    .iter()
+                    .flat_map(|&input| values[input].iter().copied())
+                    .collect(),
+                NuralGraphNodeKind::Input(size) => {
+                    let position = self
+                        .inputs
+                        .iter()
+                        .position(|&input| input == node_index)
+                        .unwrap();
+                    assert_eq!(inputs[position].len(), *size, "Graph input size mismatch");
+                    inputs[position].to_vec()
+                }
+                NuralGraphNodeKind::Layer(layer) => layer.forward(&values[node.inputs[0]]),
+            };
+        }
+        values
+    }
+
+    // Kahn's algorithm, loaded graphs are not trusted to list their nodes in dependency order.
+    fn order(&self) -> Vec<usize> {
+        let mut pending_inputs = self
+            .nodes
+            .iter()
+            .map(|node| node.inputs.len())
+            .collect::<Vec<_>>();
+        let mut consumers = vec![vec![]; self.nodes.len()];
+        for (node_index, node) in self.nodes.iter().enumerate() {
+            for &input in node.inputs.iter() {
+                consumers[input].push(node_index);
+            }
+        }
+
+        let mut ready = (0..self.nodes.len())
+            .filter(|&node_index| pending_inputs[node_index] == 0)
+            .collect::<Vec<_>>();
+        let mut order = vec![];
+        while let Some(node_index) = ready.pop() {
+            order.push(node_index);
+            for &consumer in consumers[node_index].iter() {
+                pending_inputs[consumer] -= 1;
+                if pending_inputs[consumer] == 0 {
+                    ready.push(consumer);
+                }
+            }
+        }
+
+        assert_eq!(order.len(), self.nodes.len(), "Graph contains a cycle");
+        order
+    }
+
+    fn penalty(&self) -> Float {
+        self.nodes
+            .iter()
+            .map(|node| match &node.kind {
+                NuralGraphNodeKind::Layer(layer) => layer.penalty(),
+                _ => 0.0,
+            })
+            .sum()
+    }
+
+    fn push_node(&mut self, inputs: Vec<usize>, kind: NuralGraphNodeKind) -> usize {
+        for &input in inputs.iter() {
+            assert!(input < self.nodes.len(), "Unknown graph node {}", input);
+        }
+
+        self.nodes.push(NuralGraphNode { inputs, kind });
+        self.nodes.len() - 1
+    }
+}
+
+// A node feeding several consumers receives the sum of their gradients.
+fn accumulate(gradient: &mut Option<Vec<Float>>, other: &[Float]) {
+    match gradient {
+        Some(gradient) => gradient
+            .iter_mut()
+            .zip(other.iter())
+            .for_each(|(val, other_val)| *val += other_val),
+        None => *gradient = Some(other.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::dense_layer::DenseLayer;
+    use crate::nural::gradient_check::{relative_error, GradientCheck};
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    // The hidden node feeds a layer, a residual add and a concat, so its gradient is the sum
+    // of three paths.
+    fn graph() -> NuralGraph {
+        let mut graph = NuralGraph::new(1.0, NuralNetworkLossKind::Mse);
+        let input = graph.input(3);
+        let hidden = graph.layer(input, Box::new(DenseLayer::new(3, 3)));
+        let branch = graph.layer(hidden, Box::new(DenseLayer::new(3, 3)));
+        let branch = graph.layer(
+            branch,
+            Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+        );
+        let residual = graph.add(&[hidden, branch]);
+        let joined = graph.concat(&[hidden, residual]);
+        let output = graph.layer(joined, Box::new(DenseLayer::new(6, 2)));
+        graph.output(output);
+        graph
+    }
+
+    fn parameters(graph: &NuralGraph) -> Vec<Float> {
+        graph
+            .nodes
+            .iter()
+            .flat_map(|node| match &node.kind {
+                NuralGraphNodeKind::Layer(layer) => layer
+                    .parameters()
+                    .iter()
+                    .flat_map(|parameter| parameter.iter().copied().collect::<Vec<_>>())
+                    .collect(),
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    fn shift_parameter(graph: &mut NuralGraph, parameter_index: usize, shift: Float) {
+        let value = graph
+            .nodes
+            .iter_mut()
+            .filter_map(|node| match &mut node.kind {
+                NuralGraphNodeKind::Layer(layer) => Some(layer),
+                _ => None,
+            })
+            .flat_map(|layer| layer.parameters_mut())
+            .flat_map(|parameter| parameter.into_iter())
+            .nth(parameter_index)
+            .unwrap();
+        *value += shift;
+    }
+
+    #[test]
+    fn fan_out_gradients_match_finite_differences() {
+        let (input, expected_output) = (vec![0.3, -0.7, 0.5], vec![0.2, -0.4]);
+        let initial_graph = serde_cbor::to_vec(&graph()).unwrap();
+        let initial_graph = || serde_cbor::from_slice::<NuralGraph>(&initial_graph).unwrap();
+        let loss = |graph: &NuralGraph| {
+            graph
+                .loss
+                .value(&graph.predict(&[&input])[0], &expected_output)
+        };
+
+        // With a learning rate of one a single step moves every parameter by its gradient.
+        let mut trained_graph = initial_graph();
+        trained_graph.train(&[(vec![input.clone()], vec![expected_output.clone()])], 1);
+        let gradient = parameters(&initial_graph())
+            .iter()
+            .zip(parameters(&trained_graph).iter())
+            .map(|(val, trained_val)| val - trained_val)
+            .collect::<Vec<_>>();
+
+        let step = GradientCheck::step();
+        let mut shifted_graph = initial_graph();
+        for (parameter_index, &gradient_val) in gradient.iter().enumerate() {
+            shift_parameter(&mut shifted_graph, parameter_index, step);
+            let loss_up = loss(&shifted_graph);
+            shift_parameter(&mut shifted_graph, parameter_index, -2.0 * step);
+            let loss_down = loss(&shifted_graph);
+            shift_parameter(&mut shifted_graph, parameter_index, step);
+
+            let numeric_gradient = (loss_up - loss_down) / (2.0 * step);
+            assert!(
+                relative_error(gradient_val, numeric_gradient) <= GradientCheck::tolerance(),
+                "parameter {}: {} vs {}",
+                parameter_index,
+                gradient_val,
+                numeric_gradient
+            );
+        }
+    }
+
+    #[test]
+    fn save_and_load_keep_the_graph() {
+        let file_path =
+            std::env::temp_dir().join(format!("nural_graph_{}.tnn", std::process::id()));
+        let file_path = file_path.to_str().unwrap();
+        let graph = graph();
+        graph.save_file(file_path).unwrap();
+        let loaded_graph = NuralGraph::load_file(file_path).unwrap();
+        std::fs::remove_file(file_path).unwrap();
+
+        let input = [0.3, -0.7, 0.5];
+        assert_eq!(loaded_graph.predict(&[&input]), graph.predict(&[&input]));
+        assert_eq!(parameters(&loaded_graph), parameters(&graph));
+        assert_eq!(loaded_graph.outputs, graph.outputs);
+    }
+
+    #[test]
+    #[should_panic(expected = "Concat needs at least one input")]
+    fn concat_rejects_empty_inputs() {
+        NuralGraph::new(1.0, NuralNetworkLossKind::Mse).concat(&[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Add needs at least one input")]
+    fn add_rejects_empty_inputs() {
+        NuralGraph::new(1.0, NuralNetworkLossKind::Mse).add(&[]);
+    }
+}
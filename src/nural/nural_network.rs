@@ -1,32 +1,231 @@
-﻿use crate::nural::loss_fns::{LossFn, BINARY_CROSS_ENTROPY, MSE};
+﻿use crate::nural::float::Float;
+use crate::nural::gradient_clipping::GradientClipping;
+use crate::nural::loss::{DistillationLoss, Loss};
+use crate::nural::loss_fns::{
+    expected_class, LossFn, BINARY_CROSS_ENTROPY, CROSS_ENTROPY, FOCAL, HINGE, HUBER,
+    KL_DIVERGENCE, MAE, MSE, SQUARED_HINGE,
+};
 use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::nural_network_statistics::NuralNetworkStatistics;
+use crate::nural::nural_network_summary::NuralNetworkSummary;
+use crate::nural::parameter_gradient::ParameterGradient;
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
 #[derive(Deserialize, Serialize)]
 pub struct NuralNetwork {
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+    #[serde(default)]
+    gradient_clipping: GradientClipping,
+    // One entry per layer, missing entries (networks saved before) are trainable at the base
+    // learning rate.
+    #[serde(default)]
+    layer_training: Vec<LayerTraining>,
     layers: Vec<Box<dyn NuralNetworkLayer>>,
-    learning_rate: f64,
-    loss_kind: NuralNetworkLossKind,
+    learning_rate: Float,
+    #[serde(alias = "loss_kind")]
+    loss: Box<dyn Loss>,
+    #[serde(skip)]
+    thread_pool: Option<ThreadPool>,
+}
+
+// Passed to the training callback after every epoch. The gradient norms are global norms of
+// the parameter gradients measured before clipping.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct NuralNetworkEpoch {
+    pub epoch: usize,
+    pub epochs: usize,
+    pub error: Float,
+    pub gradient_norm: Float,
+    pub max_gradient_norm: Float,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct LayerTraining {
+    frozen: bool,
+    learning_rate_multiplier: Float,
 }
 
 #[derive(Deserialize, Serialize)]
 pub enum NuralNetworkLossKind {
     BinaryCrossEntropy,
+    CrossEntropy,
+    Focal,
+    Hinge,
+    Huber,
+    KlDivergence,
+    Mae,
     Mse,
+    SquaredHinge,
 }
 
 impl NuralNetwork {
     pub fn new(
         layers: Vec<Box<dyn NuralNetworkLayer>>,
-        learning_rate: f64,
-        loss_kind: NuralNetworkLossKind,
+        learning_rate: Float,
+        loss: impl Loss + 'static,
     ) -> Self {
         NuralNetwork {
+            batch_size: default_batch_size(),
+            gradient_clipping: GradientClipping::default(),
+            layer_training: vec![LayerTraining::default(); layers.len()],
             layers,
             learning_rate,
-            loss_kind,
+            loss: Box::new(loss),
+            thread_pool: None,
+        }
+    }
+
+    // Averages the gradients of `batch_size` samples before every update, the default of one
+    // updates after every sample.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Batch size must be at least one");
+        self.batch_size = batch_size;
+        self
+    }
+
+    // Frozen layers keep their parameters during training, e.g. to fine-tune only the head of
+    // a trained network. No gradients are computed for the frozen layers below the lowest
+    // trainable one.
+    pub fn with_frozen_layers(mut self, layer_indices: impl IntoIterator<Item = usize>) -> Self {
+        for layer_index in layer_indices {
+            self.layer_training_mut(layer_index).frozen = true;
+        }
+        self
+    }
+
+    pub fn with_gradient_clipping(mut self, gradient_clipping: GradientClipping) -> Self {
+        self.gradient_clipping = gradient_clipping;
+        self
+    }
+
+    // The layer is updated with the network's learning rate times the multiplier.
+    pub fn with_layer_learning_rate_multiplier(
+        mut self,
+        layer_index: usize,
+        learning_rate_multiplier: Float,
+    ) -> Self {
+        self.layer_training_mut(layer_index)
+            .learning_rate_multiplier = learning_rate_multiplier;
+        self
+    }
+
+    // Splits training batches and `predict_batch` over this many threads. The workers are
+    // started once and kept with the network, so every mini-batch reuses them. The result does
+    // not depend on the thread count, the gradients are always summed in sample order.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "Training needs at least one thread");
+        self.thread_pool = (threads > 1).then(|| {
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("Failed to start the training threads")
+        });
+        self
+    }
+
+    pub fn with_unfrozen_layers(mut self, layer_indices: impl IntoIterator<Item = usize>) -> Self {
+        for layer_index in layer_indices {
+            self.layer_training_mut(layer_index).frozen = false;
+        }
+        self
+    }
+
+    // Share of the samples whose largest output is the expected class.
+    pub fn accuracy(&self, data: &[(Vec<Float>, Vec<Float>)]) -> Float {
+        let inputs = data
+            .iter()
+            .map(|(input, _)| input.clone())
+            .collect::<Vec<_>>();
+        let correct = self
+            .predict_batch(&inputs)
+            .iter()
+            .zip(data.iter())
+            .filter(|(output, (_, expected_output))| {
+                expected_class(output) == expected_class(expected_output)
+            })
+            .count();
+        correct as Float / data.len() as Float
+    }
+
+    pub fn is_frozen(&self, layer_index: usize) -> bool {
+        self.layer_training(layer_index).frozen
+    }
+
+    // Mean parameter gradients of the samples, per layer, without updating anything. Frozen
+    // layers get empty gradients.
+    pub fn gradients(&self, data: &[(Vec<Float>, Vec<Float>)]) -> Vec<ParameterGradient> {
+        let layer_outputs = map_threaded(data, self.thread_pool.as_ref(), |(input, _)| {
+            self.layer_outputs(input)
+        });
+        self.gradients_of_layer_outputs(data, &layer_outputs)
+    }
+
+    // Same as `gradients`, for samples whose `layer_outputs` were already computed.
+    pub fn gradients_of_layer_outputs(
+        &self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        layer_outputs: &[Vec<Vec<Float>>],
+    ) -> Vec<ParameterGradient> {
+        let batch = (0..data.len()).collect::<Vec<_>>();
+        let sample_gradients = map_threaded(&batch, self.thread_pool.as_ref(), |&sample_index| {
+            let (_, expected_output) = &data[sample_index];
+            self.sample_gradients(&layer_outputs[sample_index], expected_output, 1.0, None)
+        });
+
+        let mut parameter_gradients = vec![ParameterGradient::default(); self.layers.len()];
+        for (_, sample_parameter_gradients) in sample_gradients {
+            for (parameter_gradient, sample_parameter_gradient) in parameter_gradients
+                .iter_mut()
+                .zip(sample_parameter_gradients.iter())
+            {
+                parameter_gradient.add_assign(sample_parameter_gradient);
+            }
+        }
+        parameter_gradients
+            .iter_mut()
+            .flat_map(|parameter_gradient| parameter_gradient.values_mut())
+            .for_each(|gradient_val| *gradient_val /= data.len() as Float);
+        parameter_gradients
+    }
+
+    // Inserts the layer so it gets the index, trainable at the base learning rate.
+    pub fn insert_layer(&mut self, layer_index: usize, layer: Box<dyn NuralNetworkLayer>) {
+        if layer_index <= self.layer_training.len() {
+            self.layer_training
+                .insert(layer_index, LayerTraining::default());
+        }
+        self.layers.insert(layer_index, layer);
+    }
+
+    pub fn into_layers(self) -> Vec<Box<dyn NuralNetworkLayer>> {
+        self.layers
+    }
+
+    // The input followed by the output of every layer.
+    pub fn layer_outputs(&self, input: &[Float]) -> Vec<Vec<Float>> {
+        let mut outputs = vec![input.to_vec(); 1];
+        for layer in self.layers.iter() {
+            let output = layer.forward(outputs.last().unwrap());
+            outputs.push(output);
         }
+        outputs
+    }
+
+    pub fn layers(&self) -> &[Box<dyn NuralNetworkLayer>] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [Box<dyn NuralNetworkLayer>] {
+        &mut self.layers
+    }
+
+    pub fn learning_rate_multiplier(&self, layer_index: usize) -> Float {
+        self.layer_training(layer_index).learning_rate_multiplier
     }
 
     pub fn load_file(file_path: &str) -> Result<NuralNetwork, std::io::Error> {
@@ -34,65 +233,557 @@ impl NuralNetwork {
         Ok(serde_cbor::from_slice::<NuralNetwork>(&serialized_bytes).unwrap())
     }
 
-    pub fn predict(&self, input: &[f64]) -> Vec<f64> {
-        self.forward(input).last().unwrap().clone()
+    pub fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        self.layers
+            .iter()
+            .enumerate()
+            .try_fold(input_shape.to_vec(), |shape, (layer_index, layer)| {
+                layer
+                    .output_shape(&shape)
+                    .map_err(|err| format!("layer {}: {}", layer_index, err))
+            })
+    }
+
+    // Prediction only reads the network, so a loaded network can be shared between threads,
+    // e.g. behind an `Arc`, and queried concurrently.
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        self.layers
+            .iter()
+            .fold(input.to_vec(), |output, layer| layer.forward(&output))
+    }
+
+    // Predicts every input, split over the configured number of threads.
+    pub fn predict_batch(&self, inputs: &[Vec<Float>]) -> Vec<Vec<Float>> {
+        map_threaded(inputs, self.thread_pool.as_ref(), |input| {
+            self.predict(input)
+        })
+    }
+
+    // Removes the layer at the index together with its training settings and returns it.
+    pub fn remove_layer(&mut self, layer_index: usize) -> Box<dyn NuralNetworkLayer> {
+        if layer_index < self.layer_training.len() {
+            self.layer_training.remove(layer_index);
+        }
+        self.layers.remove(layer_index)
     }
 
+    // Swaps the layer at the index for the given layers and returns it.
+    pub fn replace_layer(
+        &mut self,
+        layer_index: usize,
+        layers: Vec<Box<dyn NuralNetworkLayer>>,
+    ) -> Box<dyn NuralNetworkLayer> {
+        if layer_index < self.layer_training.len() {
+            self.layer_training.splice(
+                layer_index..=layer_index,
+                vec![LayerTraining::default(); layers.len()],
+            );
+        }
+        self.layers
+            .splice(layer_index..=layer_index, layers)
+            .next()
+            .unwrap()
+    }
+
+    // Returns an error for a network trained with a custom `Loss`, only the built-in losses
+    // have a name to be saved under.
     pub fn save_file(&self, file_path: &str) -> Result<(), std::io::Error> {
-        let serialized_bytes = serde_cbor::to_vec(self).unwrap();
+        let serialized_bytes = serde_cbor::to_vec(self).map_err(std::io::Error::other)?;
         std::fs::write(file_path, serialized_bytes)
     }
 
-    pub fn train(&mut self, data: &[(Vec<f64>, Vec<f64>)], epochs: usize) {
-        for epoch in 0..epochs {
-            let mut error = 0.0;
+    // Weight, activation and gradient statistics of every layer over the sample batch.
+    pub fn statistics(&self, data: &[(Vec<Float>, Vec<Float>)]) -> NuralNetworkStatistics {
+        NuralNetworkStatistics::new(self, data)
+    }
+
+    // Shapes, parameter counts, FLOPs and memory of every layer for the given input shape.
+    pub fn summary(&self, input_shape: &[usize]) -> Result<NuralNetworkSummary, String> {
+        NuralNetworkSummary::new(self, input_shape)
+    }
 
-            for (input, expected_output) in data.iter() {
-                let outputs = self.forward(input);
-                let output = outputs.last().unwrap();
+    // Moves the worker threads of the other network to this one, e.g. to a network loaded
+    // from a file, which comes without threads.
+    pub fn take_threads(&mut self, other: &mut NuralNetwork) {
+        self.thread_pool = other.thread_pool.take();
+    }
 
-                error += (self.loss_fn().fx)(&output, &expected_output);
+    pub fn threads(&self) -> usize {
+        self.thread_pool
+            .as_ref()
+            .map_or(1, |thread_pool| thread_pool.current_num_threads())
+    }
 
-                let mut gradient = (self.loss_fn().dx)(&output, &expected_output);
-                for (layer_index, layer) in self.layers.iter_mut().enumerate().rev() {
-                    gradient = layer.backward(
-                        &outputs[layer_index],
-                        &outputs[layer_index + 1],
-                        &gradient,
-                        self.learning_rate,
-                    );
-                }
-            }
+    pub fn train(&mut self, data: &[(Vec<Float>, Vec<Float>)], epochs: usize) {
+        self.train_weighted(data, &vec![1.0; data.len()], epochs);
+    }
 
+    // Knowledge distillation: trains this network on the teacher's soft targets mixed with the
+    // hard labels of the data. Fails unless the network's loss is a `DistillationLoss`.
+    pub fn train_distilled(
+        &mut self,
+        teacher: &NuralNetwork,
+        data: &[(Vec<Float>, Vec<Float>)],
+        epochs: usize,
+    ) -> Result<(), String> {
+        self.train_distilled_with_callback(teacher, data, epochs, |report| {
             println!(
                 "epoch {}/{} error: {}",
+                report.epoch, report.epochs, report.error
+            )
+        })
+    }
+
+    // The samples are shuffled every epoch.
+    pub fn train_distilled_with_callback(
+        &mut self,
+        teacher: &NuralNetwork,
+        data: &[(Vec<Float>, Vec<Float>)],
+        epochs: usize,
+        mut callback: impl FnMut(&NuralNetworkEpoch),
+    ) -> Result<(), String> {
+        let distillation_loss = self
+            .distillation_loss()
+            .ok_or("Distillation needs a DistillationLoss")?;
+        let inputs = data
+            .iter()
+            .map(|(input, _)| input.clone())
+            .collect::<Vec<_>>();
+        let soft_targets = teacher
+            .predict_batch(&inputs)
+            .iter()
+            .map(|teacher_output| distillation_loss.soft_targets(teacher_output))
+            .collect::<Vec<_>>();
+
+        let sample_weights = vec![1.0; data.len()];
+        let mut order = (0..data.len()).collect::<Vec<_>>();
+        for epoch in 0..epochs {
+            order.shuffle(&mut rand::rng());
+            let report = self.train_epoch_with_soft_targets(
+                data,
+                &sample_weights,
+                Some(&soft_targets),
+                &order,
                 epoch + 1,
                 epochs,
-                error / data.len() as f64
             );
+            callback(&report);
         }
+        Ok(())
     }
 
-    fn forward(&self, input: &[f64]) -> Vec<Vec<f64>> {
-        let mut outputs = vec![input.to_vec(); 1];
-        for layer in self.layers.iter() {
-            let output = layer.forward(outputs.last().unwrap());
-            outputs.push(output);
+    // Scales the loss and gradient of every sample by its weight.
+    pub fn train_weighted(
+        &mut self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        sample_weights: &[Float],
+        epochs: usize,
+    ) {
+        self.train_with_callback(data, sample_weights, epochs, |report| {
+            println!(
+                "epoch {}/{} error: {}",
+                report.epoch, report.epochs, report.error
+            )
+        });
+    }
+
+    pub fn train_with_callback(
+        &mut self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        sample_weights: &[Float],
+        epochs: usize,
+        mut callback: impl FnMut(&NuralNetworkEpoch),
+    ) {
+        let order = (0..data.len()).collect::<Vec<_>>();
+        for epoch in 0..epochs {
+            let report = self.train_epoch(data, sample_weights, &order, epoch + 1, epochs);
+            callback(&report);
         }
-        outputs
     }
 
-    fn loss_fn(&self) -> LossFn {
-        match self.loss_kind {
+    // One pass over the samples in the given order.
+    pub fn train_epoch(
+        &mut self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        sample_weights: &[Float],
+        order: &[usize],
+        epoch: usize,
+        epochs: usize,
+    ) -> NuralNetworkEpoch {
+        self.train_epoch_with_soft_targets(data, sample_weights, None, order, epoch, epochs)
+    }
+
+    // With soft targets, one per sample, the samples are trained with the distillation loss.
+    fn train_epoch_with_soft_targets(
+        &mut self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        sample_weights: &[Float],
+        soft_targets: Option<&[Vec<Float>]>,
+        order: &[usize],
+        epoch: usize,
+        epochs: usize,
+    ) -> NuralNetworkEpoch {
+        assert_eq!(data.len(), sample_weights.len(), "Every sample needs a weight");
+
+        let mut error = 0.0;
+        let mut gradient_norm_sum = 0.0;
+        let mut max_gradient_norm: Float = 0.0;
+        let mut updates = 0;
+
+        for batch in order.chunks(self.batch_size) {
+            let mut parameter_gradients = vec![];
+            for (sample_error, sample_parameter_gradients) in
+                self.batch_gradients(data, sample_weights, soft_targets, batch)
+            {
+                error += sample_error;
+                if parameter_gradients.is_empty() {
+                    parameter_gradients = sample_parameter_gradients;
+                } else {
+                    for (parameter_gradient, sample_parameter_gradient) in parameter_gradients
+                        .iter_mut()
+                        .zip(sample_parameter_gradients.iter())
+                    {
+                        parameter_gradient.add_assign(sample_parameter_gradient);
+                    }
+                }
+            }
+            if batch.len() > 1 {
+                parameter_gradients
+                    .iter_mut()
+                    .flat_map(|parameter_gradient| parameter_gradient.values_mut())
+                    .for_each(|gradient_val| *gradient_val /= batch.len() as Float);
+            }
+
+            let gradient_norm = self.gradient_clipping.clip(&mut parameter_gradients);
+            gradient_norm_sum += gradient_norm;
+            max_gradient_norm = max_gradient_norm.max(gradient_norm);
+            updates += 1;
+
+            for (layer_index, parameter_gradient) in parameter_gradients.iter().enumerate() {
+                let layer_training = self.layer_training(layer_index);
+                if !layer_training.frozen {
+                    self.layers[layer_index].update(
+                        parameter_gradient,
+                        self.learning_rate * layer_training.learning_rate_multiplier,
+                    );
+                }
+            }
+        }
+
+        // Without data there are no updates, report zero rather than dividing by it.
+        if updates == 0 {
+            return NuralNetworkEpoch {
+                epoch,
+                epochs,
+                error: self.penalty(),
+                gradient_norm: 0.0,
+                max_gradient_norm,
+            };
+        }
+        NuralNetworkEpoch {
+            epoch,
+            epochs,
+            error: error / order.len() as Float + self.penalty(),
+            gradient_norm: gradient_norm_sum / updates as Float,
+            max_gradient_norm,
+        }
+    }
+
+    // The weighted error and the parameter gradients of every sample in the batch, in batch
+    // order.
+    fn batch_gradients(
+        &self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        sample_weights: &[Float],
+        soft_targets: Option<&[Vec<Float>]>,
+        batch: &[usize],
+    ) -> Vec<(Float, Vec<ParameterGradient>)> {
+        map_threaded(batch, self.thread_pool.as_ref(), |&sample_index| {
+            let (input, expected_output) = &data[sample_index];
+            self.sample_gradients(
+                &self.layer_outputs(input),
+                expected_output,
+                sample_weights[sample_index],
+                soft_targets.map(|soft_targets| soft_targets[sample_index].as_slice()),
+            )
+        })
+    }
+
+    fn distillation_loss(&self) -> Option<&DistillationLoss> {
+        self.loss.as_any().downcast_ref::<DistillationLoss>()
+    }
+
+    fn layer_training(&self, layer_index: usize) -> LayerTraining {
+        assert!(layer_index < self.layers.len(), "Layer index out of range");
+        self.layer_training
+            .get(layer_index)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn layer_training_mut(&mut self, layer_index: usize) -> &mut LayerTraining {
+        assert!(layer_index < self.layers.len(), "Layer index out of range");
+        self.layer_training
+            .resize(self.layers.len(), LayerTraining::default());
+        &mut self.layer_training[layer_index]
+    }
+
+    fn penalty(&self) -> Float {
+        self.layers.iter().map(|layer| layer.penalty()).sum()
+    }
+
+    // Backpropagates one sample through the recorded `layer_outputs`.
+    fn sample_gradients(
+        &self,
+        outputs: &[Vec<Float>],
+        expected_output: &[Float],
+        sample_weight: Float,
+        soft_targets: Option<&[Float]>,
+    ) -> (Float, Vec<ParameterGradient>) {
+        let output = outputs.last().unwrap();
+
+        let (loss_value, loss_gradient) = match soft_targets {
+            Some(soft_targets) => {
+                let distillation_loss = self
+                    .distillation_loss()
+                    .expect("Soft targets need a DistillationLoss");
+                (
+                    distillation_loss.distilled_value(output, expected_output, soft_targets),
+                    distillation_loss.distilled_gradient(output, expected_output, soft_targets),
+                )
+            }
+            None => (
+                self.loss.value(output, expected_output),
+                self.loss.gradient(output, expected_output),
+            ),
+        };
+        let mut gradient = loss_gradient
+            .iter()
+            .map(|gradient_val| gradient_val * sample_weight)
+            .collect::<Vec<Float>>();
+        // Frozen layers get no parameter gradient, the ones below the lowest trainable layer
+        // are skipped entirely.
+        let mut parameter_gradients = vec![ParameterGradient::default(); self.layers.len()];
+        let first_trainable_layer = (0..self.layers.len())
+            .find(|&layer_index| !self.is_frozen(layer_index))
+            .unwrap_or(self.layers.len());
+        for (layer_index, layer) in self
+            .layers
+            .iter()
+            .enumerate()
+            .skip(first_trainable_layer)
+            .rev()
+        {
+            let (input_gradient, parameter_gradient) =
+                layer.gradient(&outputs[layer_index], &outputs[layer_index + 1], &gradient);
+            gradient = input_gradient;
+            if !self.is_frozen(layer_index) {
+                parameter_gradients[layer_index] = parameter_gradient;
+            }
+        }
+
+        (sample_weight * loss_value, parameter_gradients)
+    }
+}
+
+impl Default for LayerTraining {
+    fn default() -> Self {
+        LayerTraining {
+            frozen: false,
+            learning_rate_multiplier: 1.0,
+        }
+    }
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+// Maps the items on the thread pool, or on the calling thread without one, and keeps the
+// order of the items.
+fn map_threaded<T: Sync, R: Send>(
+    items: &[T],
+    thread_pool: Option<&ThreadPool>,
+    map: impl Fn(&T) -> R + Sync + Send,
+) -> Vec<R> {
+    match thread_pool {
+        Some(thread_pool) if items.len() > 1 => {
+            thread_pool.install(|| items.par_iter().map(map).collect())
+        }
+        _ => items.iter().map(map).collect(),
+    }
+}
+
+impl NuralNetworkLossKind {
+    pub fn loss_fn(&self) -> LossFn<'static> {
+        match self {
             NuralNetworkLossKind::BinaryCrossEntropy => BINARY_CROSS_ENTROPY,
+            NuralNetworkLossKind::CrossEntropy => CROSS_ENTROPY,
+            NuralNetworkLossKind::Focal => FOCAL,
+            NuralNetworkLossKind::Hinge => HINGE,
+            NuralNetworkLossKind::Huber => HUBER,
+            NuralNetworkLossKind::KlDivergence => KL_DIVERGENCE,
+            NuralNetworkLossKind::Mae => MAE,
             NuralNetworkLossKind::Mse => MSE,
+            NuralNetworkLossKind::SquaredHinge => SQUARED_HINGE,
         }
     }
 }
 
 impl Display for NuralNetwork {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let serialized_nural_network = serde_json::to_string_pretty(&self).unwrap();
-        write!(f, "Nural Network: {}", serialized_nural_network)
+        // Without an input shape only the layers and their parameters can be listed, see
+        // `summary` for the full table.
+        let parameters = self
+            .layers
+            .iter()
+            .map(|layer| layer.parameter_count())
+            .sum::<usize>();
+        write!(
+            f,
+            "Nural Network: {} layers, {} parameters",
+            self.layers.len(),
+            parameters
+        )?;
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            write!(
+                f,
+                "\n  {} {} ({} parameters)",
+                layer_index,
+                layer.name(),
+                layer.parameter_count()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::dense_layer::DenseLayer;
+
+    fn parameters(network: &NuralNetwork) -> Vec<u64> {
+        network
+            .layers()
+            .iter()
+            .flat_map(|layer| layer.parameters())
+            .flat_map(|parameter| {
+                parameter
+                    .iter()
+                    .map(|val| val.to_bits())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thread_count_does_not_change_training() {
+        let data = (0..32)
+            .map(|index| {
+                let input = vec![(index as Float * 0.3).sin(), (index as Float * 0.7).cos()];
+                let output = vec![input[0] * input[1]];
+                (input, output)
+            })
+            .collect::<Vec<_>>();
+        let initial_network = serde_cbor::to_vec(&NuralNetwork::new(
+            vec![
+                Box::new(DenseLayer::new(2, 8)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+                Box::new(DenseLayer::new(8, 1)),
+            ],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        ))
+        .unwrap();
+        let train = |threads| {
+            let mut network = serde_cbor::from_slice::<NuralNetwork>(&initial_network)
+                .unwrap()
+                .with_batch_size(8)
+                .with_threads(threads);
+            network.train(&data, 5);
+            network
+        };
+
+        let single_threaded = train(1);
+        let multi_threaded = train(4);
+        assert_eq!(parameters(&single_threaded), parameters(&multi_threaded));
+
+        let inputs = data
+            .iter()
+            .map(|(input, _)| input.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            single_threaded.predict_batch(&inputs),
+            multi_threaded.predict_batch(&inputs)
+        );
+    }
+
+    #[test]
+    fn an_epoch_without_data_reports_zero_norms() {
+        let mut network = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 1))],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        );
+        let report = network.train_epoch(&[], &[], &[], 1, 1);
+        assert_eq!(report.gradient_norm, 0.0);
+        assert_eq!(report.max_gradient_norm, 0.0);
+        assert!(report.error.is_finite());
+    }
+
+    #[test]
+    fn distillation_reports_every_epoch() {
+        let teacher = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 3))],
+            0.05,
+            NuralNetworkLossKind::CrossEntropy,
+        );
+        let data = (0..8)
+            .map(|index| {
+                let input = vec![(index as Float * 0.37).sin(), (index as Float * 0.37).cos()];
+                let mut output = vec![0.0; 3];
+                output[index % 3] = 1.0;
+                (input, output)
+            })
+            .collect::<Vec<_>>();
+        let mut student = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 3))],
+            0.05,
+            DistillationLoss::new(2.0),
+        );
+
+        let mut reports = vec![];
+        student
+            .train_distilled_with_callback(&teacher, &data, 20, |report| {
+                reports.push(report.clone())
+            })
+            .unwrap();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| report.epoch)
+                .collect::<Vec<_>>(),
+            (1..=20).collect::<Vec<_>>()
+        );
+        assert!(reports[19].error < reports[0].error);
+    }
+
+    #[test]
+    fn distillation_needs_a_distillation_loss() {
+        let teacher = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 3))],
+            0.05,
+            NuralNetworkLossKind::CrossEntropy,
+        );
+        let mut student = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 3))],
+            0.05,
+            NuralNetworkLossKind::CrossEntropy,
+        );
+        assert!(student
+            .train_distilled(&teacher, &[(vec![0.0, 1.0], vec![1.0, 0.0, 0.0])], 1)
+            .is_err());
     }
 }
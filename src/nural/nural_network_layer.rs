@@ -1,16 +1,97 @@
 ﻿use crate::nural::activation_layer::ActivationLayer;
+use crate::nural::attention_layer::AttentionLayer;
+use crate::nural::concat_layer::ConcatLayer;
+use crate::nural::conv_2d_layer::Conv2dLayer;
+use crate::nural::conv_transpose_2d_layer::ConvTranspose2dLayer;
 use crate::nural::dense_layer::DenseLayer;
+use crate::nural::embedding_layer::EmbeddingLayer;
+use crate::nural::flatten_layer::FlattenLayer;
+use crate::nural::float::Float;
+use crate::nural::gru_layer::GruLayer;
+use crate::nural::layer_norm_layer::LayerNormLayer;
+use crate::nural::lstm_layer::LstmLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use crate::nural::positional_encoding_layer::PositionalEncodingLayer;
+use crate::nural::reshape_layer::ReshapeLayer;
+use crate::nural::rnn_layer::RnnLayer;
+use ndarray::{ArrayViewD, ArrayViewMutD};
 use serde::de::{MapAccess, Visitor};
 use serde::ser::SerializeStruct;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::any::{Any, TypeId};
 use std::fmt;
 use crate::nural::softmax_layer::SoftmaxLayer;
+use crate::nural::transformer_encoder_layer::TransformerEncoderLayer;
+use crate::nural::upsample_layer::UpsampleLayer;
 
-pub trait NuralNetworkLayer {
+// Layers are shared between the training threads, so they must be `Send + Sync`.
+pub trait NuralNetworkLayer: Send + Sync {
     fn as_any(&self) -> &dyn Any;
-    fn backward(&mut self, input: &[f64], output: &[f64], output_gradient: &[f64], learning_rate: f64) -> Vec<f64>;
-    fn forward(&self, input: &[f64]) -> Vec<f64>;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    // Computes the gradients of one sample and updates the parameters right away.
+    fn backward(&mut self, input: &[Float], output: &[Float], output_gradient: &[Float], learning_rate: Float) -> Vec<Float> {
+        let (input_gradient, parameter_gradient) = self.gradient(input, output, output_gradient);
+        self.update(&parameter_gradient, learning_rate);
+        input_gradient
+    }
+
+    // Estimated floating point operations of one forward pass, a multiply-add counts as two.
+    // Counted are the matrix products with their bias adds and the arithmetic of element-wise
+    // layers. The gate and state updates inside recurrent layers are left out, and layers
+    // that only look up or move values (embedding, flatten, reshape) count nothing.
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        0
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float>;
+
+    // Returns the gradient with respect to the input and the gradient with respect to the
+    // parameters, the latter flattened in the order of `parameters`. Layers that only touch a
+    // few rows of their parameters return a row gradient.
+    fn gradient(&self, input: &[Float], output: &[Float], output_gradient: &[Float]) -> (Vec<Float>, ParameterGradient);
+
+    // Type name shown in summaries.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>().rsplit("::").next().unwrap()
+    }
+
+    // Layers only see flat buffers, the shape is metadata used to validate how layers are
+    // chained. Element-wise layers keep the input shape.
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        Ok(input_shape.to_vec())
+    }
+
+    // Parameters the layer computes with, pruned weights are left out like they are in
+    // `flops`. The parameter gradient still covers the full `parameters` arrays.
+    fn parameter_count(&self) -> usize {
+        self.parameter_len()
+    }
+
+    // Length of the flat parameter gradient, pruned weights included.
+    fn parameter_len(&self) -> usize {
+        self.parameters().iter().map(|parameter| parameter.len()).sum()
+    }
+
+    // The trainable arrays of the layer, always in the same order.
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![]
+    }
+
+    // Regularization penalty of the current weights, added to the reported training loss.
+    fn penalty(&self) -> Float {
+        0.0
+    }
+
+    // Gradient descent step with a parameter gradient returned by `gradient`.
+    fn update(&mut self, parameter_gradient: &ParameterGradient, learning_rate: Float) {
+        parameter_gradient.apply(self.parameters_mut(), learning_rate);
+    }
 }
 
 impl Serialize for Box<dyn NuralNetworkLayer> {
@@ -35,6 +116,76 @@ impl Serialize for Box<dyn NuralNetworkLayer> {
             state.serialize_field("type", "SoftmaxLayer")?;
             state.serialize_field("data", layer.downcast_ref::<SoftmaxLayer>().unwrap())?;
             state.end()
+        } else if layer_type_id == TypeId::of::<GruLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "GruLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<GruLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<LstmLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "LstmLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<LstmLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<RnnLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "RnnLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<RnnLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<ConcatLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "ConcatLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<ConcatLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<FlattenLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "FlattenLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<FlattenLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<ReshapeLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "ReshapeLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<ReshapeLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<LayerNormLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "LayerNormLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<LayerNormLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<EmbeddingLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "EmbeddingLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<EmbeddingLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<AttentionLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "AttentionLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<AttentionLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<PositionalEncodingLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "PositionalEncodingLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<PositionalEncodingLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<TransformerEncoderLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "TransformerEncoderLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<TransformerEncoderLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<ConvTranspose2dLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "ConvTranspose2dLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<ConvTranspose2dLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<UpsampleLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "UpsampleLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<UpsampleLayer>().unwrap())?;
+            state.end()
+        } else if layer_type_id == TypeId::of::<Conv2dLayer>() {
+            let mut state = serializer.serialize_struct("Layer", 2)?;
+            state.serialize_field("type", "Conv2dLayer")?;
+            state.serialize_field("data", layer.downcast_ref::<Conv2dLayer>().unwrap())?;
+            state.end()
         } else {
             Err(serde::ser::Error::custom("Unknown Layer type"))
         }
@@ -79,6 +230,48 @@ impl<'a> Deserialize<'a> for Box<dyn NuralNetworkLayer> {
                     "SoftmaxLayer" => map
                         .next_value::<SoftmaxLayer>()
                         .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "GruLayer" => map
+                        .next_value::<GruLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "LstmLayer" => map
+                        .next_value::<LstmLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "RnnLayer" => map
+                        .next_value::<RnnLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "ConcatLayer" => map
+                        .next_value::<ConcatLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "FlattenLayer" => map
+                        .next_value::<FlattenLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "ReshapeLayer" => map
+                        .next_value::<ReshapeLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "LayerNormLayer" => map
+                        .next_value::<LayerNormLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "EmbeddingLayer" => map
+                        .next_value::<EmbeddingLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "AttentionLayer" => map
+                        .next_value::<AttentionLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "PositionalEncodingLayer" => map
+                        .next_value::<PositionalEncodingLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "TransformerEncoderLayer" => map
+                        .next_value::<TransformerEncoderLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "ConvTranspose2dLayer" => map
+                        .next_value::<ConvTranspose2dLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "UpsampleLayer" => map
+                        .next_value::<UpsampleLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
+                    "Conv2dLayer" => map
+                        .next_value::<Conv2dLayer>()
+                        .map(|l| Box::new(l) as Box<dyn NuralNetworkLayer>),
                     _ => Err(de::Error::custom("Unknown Layer type")),
                 }
             }
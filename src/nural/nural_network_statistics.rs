@@ -0,0 +1,280 @@
+﻿use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::float::Float;
+use crate::nural::nural_network::NuralNetwork;
+use std::fmt::{Display, Formatter};
+
+const HISTOGRAM_BINS: usize = 10;
+// Tanh outputs beyond ±0.99 and sigmoid outputs below 0.01 or above 0.99 count as saturated,
+// their derivative is close to zero there.
+const SATURATION: Float = 0.99;
+
+// Weight, activation and gradient statistics of every layer over a sample batch, to see why a
+// network trains slowly: dead ReLUs, saturated tanh/sigmoid units and vanishing gradients.
+pub struct NuralNetworkStatistics {
+    pub layers: Vec<LayerStatistics>,
+}
+
+pub struct LayerStatistics {
+    pub activations: Option<ActivationStatistics>,
+    // Norm of the mean parameter gradient over the batch.
+    pub gradient_norm: Float,
+    pub name: &'static str,
+    // One entry per parameter array, in the order of `parameters`, so biases and weights are
+    // reported apart.
+    pub parameters: Vec<ParameterStatistics>,
+}
+
+pub struct ActivationStatistics {
+    // Share of the outputs that are zero for every sample.
+    pub dead: Option<Float>,
+    pub kind: &'static str,
+    // Share of the outputs, over all samples, in the flat regions of the activation.
+    pub saturated: Option<Float>,
+}
+
+// Over one parameter array. The histogram splits [min, max] into equal bins.
+pub struct ParameterStatistics {
+    pub histogram: Vec<usize>,
+    pub max: Float,
+    pub mean: Float,
+    pub min: Float,
+    pub shape: Vec<usize>,
+    pub std: Float,
+}
+
+impl NuralNetworkStatistics {
+    pub fn new(
+        network: &NuralNetwork,
+        data: &[(Vec<Float>, Vec<Float>)],
+    ) -> NuralNetworkStatistics {
+        let layer_outputs = data
+            .iter()
+            .map(|(input, _)| network.layer_outputs(input))
+            .collect::<Vec<_>>();
+
+        let layers = network
+            .layers()
+            .iter()
+            .enumerate()
+            .zip(network.gradients_of_layer_outputs(data, &layer_outputs))
+            .map(|((layer_index, layer), parameter_gradient)| {
+                let outputs = layer_outputs
+                    .iter()
+                    .map(|sample_outputs| sample_outputs[layer_index + 1].as_slice())
+                    .collect::<Vec<_>>();
+                LayerStatistics {
+                    activations: layer.as_any().downcast_ref::<ActivationLayer>().map(
+                        |activation_layer| {
+                            ActivationStatistics::new(activation_layer.kind(), &outputs)
+                        },
+                    ),
+                    gradient_norm: parameter_gradient
+                        .values()
+                        .map(|gradient_val| gradient_val * gradient_val)
+                        .sum::<Float>()
+                        .sqrt(),
+                    name: layer.name(),
+                    parameters: layer
+                        .parameters()
+                        .iter()
+                        .map(|parameter| {
+                            ParameterStatistics::new(
+                                parameter.shape(),
+                                &parameter.iter().copied().collect::<Vec<_>>(),
+                            )
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        NuralNetworkStatistics { layers }
+    }
+}
+
+impl ActivationStatistics {
+    fn new(kind: &ActivationLayerKind, outputs: &[&[Float]]) -> ActivationStatistics {
+        let output_len = outputs.first().map_or(0, |output| output.len());
+        let value_count = (outputs.len() * output_len).max(1) as Float;
+        match kind {
+            ActivationLayerKind::ReLu => {
+                let dead = (0..output_len)
+                    .filter(|&unit| outputs.iter().all(|output| output[unit] <= 0.0))
+                    .count();
+                ActivationStatistics {
+                    dead: Some(dead as Float / output_len.max(1) as Float),
+                    kind: "ReLu",
+                    saturated: None,
+                }
+            }
+            ActivationLayerKind::Sigmoid => {
+                let saturated = outputs
+                    .iter()
+                    .flat_map(|output| output.iter())
+                    .filter(|val| !(1.0 - SATURATION..=SATURATION).contains(*val))
+                    .count();
+                ActivationStatistics {
+                    dead: None,
+                    kind: "Sigmoid",
+                    saturated: Some(saturated as Float / value_count),
+                }
+            }
+            ActivationLayerKind::Tanh => {
+                let saturated = outputs
+                    .iter()
+                    .flat_map(|output| output.iter())
+                    .filter(|val| val.abs() > SATURATION)
+                    .count();
+                ActivationStatistics {
+                    dead: None,
+                    kind: "Tanh",
+                    saturated: Some(saturated as Float / value_count),
+                }
+            }
+        }
+    }
+}
+
+impl ParameterStatistics {
+    fn new(shape: &[usize], values: &[Float]) -> ParameterStatistics {
+        let mean = values.iter().sum::<Float>() / values.len() as Float;
+        let variance =
+            values.iter().map(|val| (val - mean).powi(2)).sum::<Float>() / values.len() as Float;
+        let min = values.iter().copied().fold(Float::INFINITY, Float::min);
+        let max = values.iter().copied().fold(Float::NEG_INFINITY, Float::max);
+
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        let bin_width = (max - min) / HISTOGRAM_BINS as Float;
+        for val in values.iter() {
+            let bin = if bin_width > 0.0 {
+                (((val - min) / bin_width) as usize).min(HISTOGRAM_BINS - 1)
+            } else {
+                0
+            };
+            histogram[bin] += 1;
+        }
+
+        ParameterStatistics {
+            histogram,
+            max,
+            mean,
+            min,
+            shape: shape.to_vec(),
+            std: variance.sqrt(),
+        }
+    }
+}
+
+impl Display for NuralNetworkStatistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            write!(f, "{} {}", layer_index, layer.name)?;
+            if let Some(activations) = &layer.activations {
+                write!(f, " ({})", activations.kind)?;
+                if let Some(dead) = activations.dead {
+                    write!(f, "  dead units: {:.1}%", 100.0 * dead)?;
+                }
+                if let Some(saturated) = activations.saturated {
+                    write!(f, "  saturated: {:.1}%", 100.0 * saturated)?;
+                }
+            }
+            if !layer.parameters.is_empty() {
+                write!(f, "  gradient norm: {:.3e}", layer.gradient_norm)?;
+            }
+            writeln!(f)?;
+            for parameter in layer.parameters.iter() {
+                writeln!(
+                    f,
+                    "    {:?} mean: {:.4}  std: {:.4}  min: {:.4}  max: {:.4}  histogram: {}",
+                    parameter.shape,
+                    parameter.mean,
+                    parameter.std,
+                    parameter.min,
+                    parameter.max,
+                    histogram_bar(&parameter.histogram)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// One block character per bin, scaled to the fullest bin.
+fn histogram_bar(histogram: &[usize]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max_count = histogram.iter().copied().max().unwrap_or(0).max(1);
+    histogram
+        .iter()
+        .map(|&count| {
+            if count == 0 {
+                ' '
+            } else {
+                BLOCKS[count * (BLOCKS.len() - 1) / max_count]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::dense_layer::DenseLayer;
+    use crate::nural::nural_network::NuralNetworkLossKind;
+    use ndarray::Array2;
+
+    // The first unit is always far below zero, the second follows the first input.
+    fn network(kind: ActivationLayerKind) -> NuralNetwork {
+        let weights = Array2::from_shape_vec((2, 2), vec![0.0, 0.0, 1.0, 0.0]).unwrap();
+        NuralNetwork::new(
+            vec![
+                Box::new(DenseLayer::from_weights(weights, vec![-10.0, 0.0])),
+                Box::new(ActivationLayer::new(kind)),
+            ],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        )
+    }
+
+    fn data() -> Vec<(Vec<Float>, Vec<Float>)> {
+        [-0.5, 0.25, 0.5, 1.0]
+            .iter()
+            .map(|&input| (vec![input, 1.0], vec![0.0, 0.0]))
+            .collect()
+    }
+
+    #[test]
+    fn dead_relus_are_zero_for_every_sample() {
+        let statistics = network(ActivationLayerKind::ReLu).statistics(&data());
+        let activations = statistics.layers[1].activations.as_ref().unwrap();
+        // The second unit is zero for the negative input only, so it is alive.
+        assert_eq!(activations.dead, Some(0.5));
+        assert_eq!(activations.saturated, None);
+    }
+
+    #[test]
+    fn saturated_units_are_counted_over_all_samples() {
+        for kind in [ActivationLayerKind::Tanh, ActivationLayerKind::Sigmoid] {
+            let statistics = network(kind).statistics(&data());
+            let activations = statistics.layers[1].activations.as_ref().unwrap();
+            // The first unit saturates for every sample, the second for none.
+            assert_eq!(activations.saturated, Some(0.5));
+            assert_eq!(activations.dead, None);
+        }
+    }
+
+    #[test]
+    fn parameters_are_reported_per_array() {
+        let statistics = network(ActivationLayerKind::ReLu).statistics(&data());
+        let parameters = &statistics.layers[0].parameters;
+        assert_eq!(parameters[0].shape, vec![2, 1]);
+        assert_eq!(parameters[1].shape, vec![2, 2]);
+
+        let weights = &parameters[1];
+        assert_eq!(weights.mean, 0.25);
+        assert_eq!(weights.std, (0.75 as Float).sqrt() / 2.0);
+        assert_eq!((weights.min, weights.max), (0.0, 1.0));
+        assert_eq!(weights.histogram.iter().sum::<usize>(), 4);
+        assert_eq!(weights.histogram[0], 3);
+        assert_eq!(weights.histogram[HISTOGRAM_BINS - 1], 1);
+        assert!(statistics.layers[1].parameters.is_empty());
+    }
+}
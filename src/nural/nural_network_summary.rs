@@ -0,0 +1,235 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network::NuralNetwork;
+use std::fmt::{Display, Formatter};
+
+// Per-layer overview of a network for one input shape. Parameters of frozen layers count as
+// non-trainable, the memory is the size of the layer's output for one sample.
+pub struct NuralNetworkSummary {
+    pub layers: Vec<LayerSummary>,
+}
+
+pub struct LayerSummary {
+    pub activation_memory: usize,
+    pub flops: usize,
+    pub input_shape: Vec<usize>,
+    pub name: &'static str,
+    pub non_trainable_parameters: usize,
+    pub output_shape: Vec<usize>,
+    pub trainable_parameters: usize,
+}
+
+impl NuralNetworkSummary {
+    pub fn new(
+        network: &NuralNetwork,
+        input_shape: &[usize],
+    ) -> Result<NuralNetworkSummary, String> {
+        let mut shape = input_shape.to_vec();
+        let mut layers = vec![];
+        for (layer_index, layer) in network.layers().iter().enumerate() {
+            let output_shape = layer
+                .output_shape(&shape)
+                .map_err(|err| format!("layer {}: {}", layer_index, err))?;
+            let parameters = layer.parameter_count();
+            let frozen = network.is_frozen(layer_index);
+            layers.push(LayerSummary {
+                activation_memory: output_shape.iter().product::<usize>()
+                    * std::mem::size_of::<Float>(),
+                flops: layer.flops(&shape),
+                input_shape: shape,
+                name: layer.name(),
+                non_trainable_parameters: if frozen { parameters } else { 0 },
+                output_shape: output_shape.clone(),
+                trainable_parameters: if frozen { 0 } else { parameters },
+            });
+            shape = output_shape;
+        }
+        Ok(NuralNetworkSummary { layers })
+    }
+
+    pub fn activation_memory(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.activation_memory)
+            .sum()
+    }
+
+    pub fn flops(&self) -> usize {
+        self.layers.iter().map(|layer| layer.flops).sum()
+    }
+
+    pub fn non_trainable_parameters(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.non_trainable_parameters)
+            .sum()
+    }
+
+    pub fn parameter_memory(&self) -> usize {
+        (self.trainable_parameters() + self.non_trainable_parameters())
+            * std::mem::size_of::<Float>()
+    }
+
+    pub fn trainable_parameters(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.trainable_parameters)
+            .sum()
+    }
+}
+
+impl Display for NuralNetworkSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let header = [
+            "#",
+            "Layer",
+            "Input",
+            "Output",
+            "Trainable",
+            "Non-trainable",
+            "FLOPs",
+            "Memory",
+        ]
+        .map(String::from);
+        let mut rows = vec![header];
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            rows.push([
+                layer_index.to_string(),
+                layer.name.to_string(),
+                format!("{:?}", layer.input_shape),
+                format!("{:?}", layer.output_shape),
+                layer.trainable_parameters.to_string(),
+                layer.non_trainable_parameters.to_string(),
+                layer.flops.to_string(),
+                format_bytes(layer.activation_memory),
+            ]);
+        }
+        rows.push([
+            String::new(),
+            "Total".to_string(),
+            String::new(),
+            String::new(),
+            self.trainable_parameters().to_string(),
+            self.non_trainable_parameters().to_string(),
+            self.flops().to_string(),
+            format_bytes(self.activation_memory()),
+        ]);
+
+        let widths = (0..rows[0].len())
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+            .collect::<Vec<_>>();
+        let separator = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+        for (row_index, row) in rows.iter().enumerate() {
+            if row_index == 1 || row_index == rows.len() - 1 {
+                writeln!(f, "{}", separator)?;
+            }
+            // Names and shapes are left aligned, the numbers right aligned.
+            let cells = row
+                .iter()
+                .zip(widths.iter())
+                .enumerate()
+                .map(|(column, (cell, &width))| {
+                    if (1..=3).contains(&column) {
+                        format!("{:<width$}", cell)
+                    } else {
+                        format!("{:>width$}", cell)
+                    }
+                })
+                .collect::<Vec<_>>();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        write!(
+            f,
+            "Parameter memory: {}",
+            format_bytes(self.parameter_memory())
+        )
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::conv_2d_layer::Conv2dLayer;
+    use crate::nural::dense_layer::DenseLayer;
+    use crate::nural::flatten_layer::FlattenLayer;
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    fn network() -> NuralNetwork {
+        NuralNetwork::new(
+            vec![
+                Box::new(Conv2dLayer::new([1, 6, 6], 2, 3)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::ReLu)),
+                Box::new(FlattenLayer::new()),
+                Box::new(DenseLayer::new(2 * 4 * 4, 3)),
+            ],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        )
+    }
+
+    #[test]
+    fn totals_add_up_the_layers() {
+        let summary = network().summary(&[1, 6, 6]).unwrap();
+        assert_eq!(
+            summary
+                .layers
+                .iter()
+                .map(|layer| layer.name)
+                .collect::<Vec<_>>(),
+            vec![
+                "Conv2dLayer",
+                "ActivationLayer",
+                "FlattenLayer",
+                "DenseLayer"
+            ]
+        );
+        assert_eq!(summary.layers[0].output_shape, vec![2, 4, 4]);
+        assert_eq!(summary.layers[3].input_shape, vec![32]);
+
+        // Convolution: 2 × 3 × 3 weights and 2 biases, every one of the 2 × 4 × 4 outputs
+        // takes 9 multiply-adds and a bias add.
+        assert_eq!(summary.layers[0].trainable_parameters, 20);
+        assert_eq!(summary.layers[0].flops, 32 * (2 * 9 + 1));
+        // Dense: 32 × 3 weights and 3 biases.
+        assert_eq!(summary.layers[3].trainable_parameters, 99);
+        assert_eq!(summary.layers[3].flops, 2 * 96 + 3);
+
+        assert_eq!(summary.trainable_parameters(), 119);
+        assert_eq!(summary.non_trainable_parameters(), 0);
+        assert_eq!(summary.flops(), 608 + 32 + 195);
+        assert_eq!(
+            summary.activation_memory(),
+            (32 + 32 + 32 + 3) * std::mem::size_of::<Float>()
+        );
+        assert_eq!(
+            summary.parameter_memory(),
+            119 * std::mem::size_of::<Float>()
+        );
+    }
+
+    #[test]
+    fn frozen_layers_are_not_trainable() {
+        let summary = network()
+            .with_frozen_layers([0])
+            .summary(&[1, 6, 6])
+            .unwrap();
+        assert_eq!(summary.trainable_parameters(), 99);
+        assert_eq!(summary.non_trainable_parameters(), 20);
+    }
+
+    #[test]
+    fn wrong_input_shapes_name_the_layer() {
+        let err = network().summary(&[1, 5, 5]).err().unwrap();
+        assert!(err.starts_with("layer 0:"), "{}", err);
+    }
+}
@@ -0,0 +1,341 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network::{NuralNetwork, NuralNetworkEpoch};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// A resumable training run. The samples are shuffled every epoch with a generator seeded
+// from `seed` and the epoch, so a run resumed from a checkpoint sees exactly the sample
+// order it would have seen without the interruption. The network carries the rest of the
+// training state (learning rate, loss and clipping), plain SGD keeps no optimizer state.
+#[derive(Deserialize, Serialize)]
+pub struct NuralNetworkTraining {
+    // Epochs and errors of the checkpoints still on disk, used for retention.
+    checkpoints: Vec<(usize, Float)>,
+    epoch: usize,
+    history: Vec<NuralNetworkEpoch>,
+    network: NuralNetwork,
+    seed: u64,
+}
+
+// Where and how often checkpoints are written. After every checkpoint only the newest
+// `keep_last` and the `keep_best` with the lowest error are kept.
+#[derive(Clone)]
+pub struct CheckpointPolicy {
+    directory: PathBuf,
+    every: usize,
+    keep_best: usize,
+    keep_last: usize,
+}
+
+impl NuralNetworkTraining {
+    pub fn new(network: NuralNetwork, seed: u64) -> NuralNetworkTraining {
+        NuralNetworkTraining {
+            checkpoints: vec![],
+            epoch: 0,
+            history: vec![],
+            network,
+            seed,
+        }
+    }
+
+    // Continues the run saved in the checkpoint instead of starting this one. The checkpoint
+    // has to hold the same layers with the same parameter shapes as this network, otherwise
+    // it belongs to another architecture and is rejected. The threads of this network are
+    // kept, checkpoints do not save them.
+    pub fn resume_from(mut self, file_path: &Path) -> Result<NuralNetworkTraining, std::io::Error> {
+        let serialized_bytes = std::fs::read(file_path)?;
+        let mut training: NuralNetworkTraining =
+            serde_cbor::from_slice(&serialized_bytes).map_err(std::io::Error::other)?;
+        if architecture(&training.network) != architecture(&self.network) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint {} does not match the network layers",
+                    file_path.display()
+                ),
+            ));
+        }
+        training.network.take_threads(&mut self.network);
+        Ok(training)
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    pub fn history(&self) -> &[NuralNetworkEpoch] {
+        &self.history
+    }
+
+    pub fn into_network(self) -> NuralNetwork {
+        self.network
+    }
+
+    pub fn network(&self) -> &NuralNetwork {
+        &self.network
+    }
+
+    // Written to a temporary file first so a crash while saving leaves the previous
+    // checkpoint intact.
+    pub fn save_file(&self, file_path: &Path) -> Result<(), std::io::Error> {
+        let serialized_bytes = serde_cbor::to_vec(self).map_err(std::io::Error::other)?;
+        let temp_path = file_path.with_extension("tmp");
+        std::fs::write(&temp_path, serialized_bytes)?;
+        std::fs::rename(temp_path, file_path)
+    }
+
+    // Trains until `epochs` epochs are done in total, counting the epochs before a resume.
+    pub fn train(
+        &mut self,
+        data: &[(Vec<Float>, Vec<Float>)],
+        epochs: usize,
+        checkpoint_policy: &CheckpointPolicy,
+    ) -> Result<(), std::io::Error> {
+        std::fs::create_dir_all(&checkpoint_policy.directory)?;
+        let sample_weights = vec![1.0; data.len()];
+
+        while self.epoch < epochs {
+            let mut order = (0..data.len()).collect::<Vec<_>>();
+            order.shuffle(&mut StdRng::seed_from_u64(
+                self.seed.wrapping_add(self.epoch as u64),
+            ));
+
+            let report =
+                self.network
+                    .train_epoch(data, &sample_weights, &order, self.epoch + 1, epochs);
+            println!(
+                "epoch {}/{} error: {}",
+                report.epoch, report.epochs, report.error
+            );
+            self.epoch += 1;
+            self.history.push(report);
+
+            if self.epoch.is_multiple_of(checkpoint_policy.every) || self.epoch == epochs {
+                self.checkpoint(checkpoint_policy)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self, checkpoint_policy: &CheckpointPolicy) -> Result<(), std::io::Error> {
+        let error = self.history.last().unwrap().error;
+        self.checkpoints.push((self.epoch, error));
+
+        let mut by_error = self.checkpoints.clone();
+        by_error.sort_by(|(_, error), (_, other_error)| error.total_cmp(other_error));
+        let best = by_error
+            .iter()
+            .take(checkpoint_policy.keep_best)
+            .map(|&(epoch, _)| epoch)
+            .collect::<Vec<_>>();
+        let last = self
+            .checkpoints
+            .iter()
+            .rev()
+            .take(checkpoint_policy.keep_last.max(1))
+            .map(|&(epoch, _)| epoch)
+            .collect::<Vec<_>>();
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.checkpoints)
+            .into_iter()
+            .partition(|(epoch, _)| best.contains(epoch) || last.contains(epoch));
+        self.checkpoints = kept;
+
+        self.save_file(&checkpoint_policy.path(self.epoch))?;
+        for (epoch, _) in removed {
+            remove_checkpoint(&checkpoint_policy.path(epoch))?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckpointPolicy {
+    pub fn new(directory: impl Into<PathBuf>) -> CheckpointPolicy {
+        CheckpointPolicy {
+            directory: directory.into(),
+            every: 1,
+            keep_best: 0,
+            keep_last: 1,
+        }
+    }
+
+    pub fn with_every(mut self, every: usize) -> CheckpointPolicy {
+        assert!(every > 0, "Checkpoint interval must be at least one epoch");
+        self.every = every;
+        self
+    }
+
+    pub fn with_keep_best(mut self, keep_best: usize) -> CheckpointPolicy {
+        self.keep_best = keep_best;
+        self
+    }
+
+    // The newest checkpoint is always kept, so this is at least one.
+    pub fn with_keep_last(mut self, keep_last: usize) -> CheckpointPolicy {
+        self.keep_last = keep_last;
+        self
+    }
+
+    // Removes the checkpoints of earlier runs, so a fresh run does not leave them behind to
+    // be resumed later.
+    pub fn clear(&self) -> Result<(), std::io::Error> {
+        self.checkpoints()
+            .iter()
+            .try_for_each(|path| remove_checkpoint(path))
+    }
+
+    // The newest checkpoint in the directory, if any.
+    pub fn latest(&self) -> Option<PathBuf> {
+        self.checkpoints().into_iter().max()
+    }
+
+    fn checkpoints(&self) -> Vec<PathBuf> {
+        std::fs::read_dir(&self.directory)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| {
+                                name.starts_with("epoch_") && name.ends_with(".tnn")
+                            })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn path(&self, epoch: usize) -> PathBuf {
+        self.directory.join(format!("epoch_{:06}.tnn", epoch))
+    }
+}
+
+// Layer names and parameter shapes, what a checkpoint has to agree on to be resumed.
+fn architecture(network: &NuralNetwork) -> Vec<(&'static str, Vec<Vec<usize>>)> {
+    network
+        .layers()
+        .iter()
+        .map(|layer| {
+            let shapes = layer
+                .parameters()
+                .iter()
+                .map(|parameter| parameter.shape().to_vec())
+                .collect();
+            (layer.name(), shapes)
+        })
+        .collect()
+}
+
+// A checkpoint that is already gone needs no removing.
+fn remove_checkpoint(file_path: &Path) -> Result<(), std::io::Error> {
+    match std::fs::remove_file(file_path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::dense_layer::DenseLayer;
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    fn network() -> NuralNetwork {
+        NuralNetwork::new(
+            vec![
+                Box::new(DenseLayer::new(2, 4)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+                Box::new(DenseLayer::new(4, 1)),
+            ],
+            0.1,
+            NuralNetworkLossKind::Mse,
+        )
+        .with_batch_size(2)
+    }
+
+    fn parameters(network: &NuralNetwork) -> Vec<u64> {
+        network
+            .layers()
+            .iter()
+            .flat_map(|layer| layer.parameters())
+            .flat_map(|parameter| {
+                parameter
+                    .iter()
+                    .map(|val| val.to_bits())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let data = vec![
+            (vec![0.0, 0.0], vec![0.0]),
+            (vec![0.0, 1.0], vec![1.0]),
+            (vec![1.0, 0.0], vec![1.0]),
+            (vec![1.0, 1.0], vec![0.0]),
+        ];
+        let directory = std::env::temp_dir().join(format!("nural_training_{}", std::process::id()));
+        let checkpoint_policy = CheckpointPolicy::new(&directory).with_every(3);
+        let initial_network = serde_cbor::to_vec(&network()).unwrap();
+        let initial_network = || serde_cbor::from_slice::<NuralNetwork>(&initial_network).unwrap();
+
+        let mut uninterrupted = NuralNetworkTraining::new(initial_network(), 7);
+        uninterrupted.train(&data, 6, &checkpoint_policy).unwrap();
+
+        checkpoint_policy.clear().unwrap();
+        let mut interrupted = NuralNetworkTraining::new(initial_network(), 7);
+        interrupted.train(&data, 3, &checkpoint_policy).unwrap();
+        let mut resumed = NuralNetworkTraining::new(initial_network(), 7)
+            .resume_from(&checkpoint_policy.latest().unwrap())
+            .unwrap();
+        assert_eq!(resumed.epoch(), 3);
+        resumed.train(&data, 6, &checkpoint_policy).unwrap();
+
+        assert_eq!(
+            parameters(resumed.network()),
+            parameters(uninterrupted.network())
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn resume_keeps_the_threads() {
+        let directory = std::env::temp_dir().join(format!("nural_threads_{}", std::process::id()));
+        let checkpoint_policy = CheckpointPolicy::new(&directory);
+        let mut training = NuralNetworkTraining::new(network(), 0);
+        training
+            .train(&[(vec![0.0, 1.0], vec![1.0])], 1, &checkpoint_policy)
+            .unwrap();
+
+        let resumed = NuralNetworkTraining::new(network().with_threads(3), 0)
+            .resume_from(&checkpoint_policy.latest().unwrap())
+            .unwrap();
+        assert_eq!(resumed.network().threads(), 3);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn resume_rejects_other_architectures() {
+        let directory = std::env::temp_dir().join(format!("nural_resume_{}", std::process::id()));
+        let checkpoint_policy = CheckpointPolicy::new(&directory);
+        let mut training = NuralNetworkTraining::new(network(), 0);
+        training
+            .train(&[(vec![0.0, 1.0], vec![1.0])], 1, &checkpoint_policy)
+            .unwrap();
+
+        let other_network = NuralNetwork::new(
+            vec![Box::new(DenseLayer::new(2, 1))],
+            0.1,
+            NuralNetworkLossKind::Mse,
+        );
+        let resumed = NuralNetworkTraining::new(other_network, 0)
+            .resume_from(&checkpoint_policy.latest().unwrap());
+        assert!(resumed.is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+﻿use crate::nural::float::Float;
+use ndarray::ArrayViewMutD;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+// The parameter gradient of a layer, flattened in the order of `parameters`. Layers whose
+// gradient only touches a few rows of a large parameter (embeddings) keep just those rows, so
+// adding and applying it costs in proportion to the rows of the sample, not the parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterGradient {
+    Dense(Vec<Float>),
+    // Rows of `row_len` values of the flat gradient by row index, every other value is zero.
+    Rows {
+        row_len: usize,
+        rows: BTreeMap<usize, Vec<Float>>,
+    },
+}
+
+impl ParameterGradient {
+    pub fn rows(row_len: usize) -> ParameterGradient {
+        ParameterGradient::Rows {
+            row_len,
+            rows: BTreeMap::new(),
+        }
+    }
+
+    // Adds the gradient of one row, only valid on a row gradient.
+    pub fn add_row(&mut self, row: usize, row_gradient: &[Float]) {
+        match self {
+            ParameterGradient::Rows { row_len, rows } => {
+                assert_eq!(row_gradient.len(), *row_len, "Row gradient length mismatch");
+                rows.entry(row)
+                    .and_modify(|gradient| add_assign(gradient, row_gradient))
+                    .or_insert_with(|| row_gradient.to_vec());
+            }
+            ParameterGradient::Dense(_) => panic!("Rows can only be added to a row gradient"),
+        }
+    }
+
+    // Sums the gradients of two samples of the same layer. An empty gradient takes the other.
+    pub fn add_assign(&mut self, other: &ParameterGradient) {
+        if self.is_empty() {
+            *self = other.clone();
+            return;
+        }
+
+        match (self, other) {
+            (ParameterGradient::Dense(values), ParameterGradient::Dense(other_values)) => {
+                add_assign(values, other_values)
+            }
+            (this @ ParameterGradient::Rows { .. }, ParameterGradient::Rows { rows, .. }) => {
+                for (&row, row_gradient) in rows.iter() {
+                    this.add_row(row, row_gradient);
+                }
+            }
+            _ => panic!("Dense and row gradients cannot be added"),
+        }
+    }
+
+    // Gradient descent step on the parameters, what `NuralNetworkLayer::update` does unless a
+    // layer has its own update rule. A row gradient only visits its rows.
+    pub fn apply(&self, parameters: Vec<ArrayViewMutD<'_, Float>>, learning_rate: Float) {
+        match self {
+            ParameterGradient::Dense(values) => {
+                let mut gradient_vals = values.iter();
+                for mut parameter in parameters {
+                    parameter
+                        .iter_mut()
+                        .zip(gradient_vals.by_ref())
+                        .for_each(|(val, gradient_val)| *val -= gradient_val * learning_rate);
+                }
+            }
+            ParameterGradient::Rows { row_len, rows } => {
+                let mut parameters = parameters;
+                let mut offset = 0;
+                let mut row_gradients = rows.iter().peekable();
+                for parameter in parameters.iter_mut() {
+                    let len = parameter.len();
+                    let parameter = parameter
+                        .as_slice_mut()
+                        .expect("Row gradients need parameters in standard layout");
+                    while let Some((&row, row_gradient)) =
+                        row_gradients.next_if(|(&row, _)| row * row_len < offset + len)
+                    {
+                        let start = row * row_len - offset;
+                        parameter[start..start + row_len]
+                            .iter_mut()
+                            .zip(row_gradient.iter())
+                            .for_each(|(val, gradient_val)| *val -= gradient_val * learning_rate);
+                    }
+                    offset += len;
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ParameterGradient::Dense(values) => values.is_empty(),
+            ParameterGradient::Rows { .. } => false,
+        }
+    }
+
+    // The flat gradient of `len` values, borrowed when it already is dense.
+    pub fn to_dense(&self, len: usize) -> Cow<'_, [Float]> {
+        match self {
+            ParameterGradient::Dense(values) => Cow::Borrowed(values),
+            ParameterGradient::Rows { row_len, rows } => {
+                let mut values = vec![0.0; len];
+                for (&row, row_gradient) in rows.iter() {
+                    values[row * row_len..(row + 1) * row_len].copy_from_slice(row_gradient);
+                }
+                Cow::Owned(values)
+            }
+        }
+    }
+
+    // The stored values, the zeros a row gradient leaves out are skipped. Enough for norms
+    // and for scaling or clipping every value.
+    pub fn values(&self) -> Box<dyn Iterator<Item = &Float> + '_> {
+        match self {
+            ParameterGradient::Dense(values) => Box::new(values.iter()),
+            ParameterGradient::Rows { rows, .. } => Box::new(rows.values().flatten()),
+        }
+    }
+
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut Float> + '_> {
+        match self {
+            ParameterGradient::Dense(values) => Box::new(values.iter_mut()),
+            ParameterGradient::Rows { rows, .. } => Box::new(rows.values_mut().flatten()),
+        }
+    }
+}
+
+impl Default for ParameterGradient {
+    fn default() -> Self {
+        ParameterGradient::Dense(vec![])
+    }
+}
+
+impl From<Vec<Float>> for ParameterGradient {
+    fn from(values: Vec<Float>) -> Self {
+        ParameterGradient::Dense(values)
+    }
+}
+
+impl FromIterator<Float> for ParameterGradient {
+    fn from_iter<I: IntoIterator<Item = Float>>(iter: I) -> Self {
+        ParameterGradient::Dense(iter.into_iter().collect())
+    }
+}
+
+fn add_assign(values: &mut [Float], other: &[Float]) {
+    values
+        .iter_mut()
+        .zip(other.iter())
+        .for_each(|(val, other_val)| *val += other_val);
+}
@@ -0,0 +1,90 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Adds the fixed sinusoidal position signal to every step of a `[steps, features]`
+// sequence so attention can tell the steps apart.
+#[derive(Deserialize, Serialize)]
+pub struct PositionalEncodingLayer {
+    features: usize,
+}
+
+impl PositionalEncodingLayer {
+    pub fn new(features: usize) -> PositionalEncodingLayer {
+        PositionalEncodingLayer { features }
+    }
+
+    fn encoding(&self, step: usize, feature: usize) -> Float {
+        let frequency = (10000.0 as Float).powf((feature - feature % 2) as Float / self.features as Float);
+        let angle = step as Float / frequency;
+        if feature.is_multiple_of(2) {
+            angle.sin()
+        } else {
+            angle.cos()
+        }
+    }
+}
+
+impl NuralNetworkLayer for PositionalEncodingLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(index, val)| val + self.encoding(index / self.features, index % self.features))
+            .collect()
+    }
+
+    fn gradient(
+        &self,
+        _input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        (output_gradient.to_vec(), ParameterGradient::default())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape {
+            [_, features] if *features == self.features => Ok(input_shape.to_vec()),
+            _ => Err(format!(
+                "PositionalEncodingLayer expects input shape [steps, {}], got {:?}",
+                self.features, input_shape
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = PositionalEncodingLayer::new(8);
+        let input = (0..3 * 8)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        let check = gradient_check(&mut layer, &input);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+}
@@ -0,0 +1,231 @@
+﻿use crate::nural::dense_layer::DenseLayer;
+use crate::nural::float::Float;
+use crate::nural::nural_network::NuralNetwork;
+
+// Iterative magnitude pruning of the dense layers. The sparsity is raised in equal steps up to
+// the target, every step prunes the smallest weights and then fine-tunes the network so the
+// remaining weights can make up for the removed ones.
+pub struct MagnitudePruning {
+    fine_tune_epochs: usize,
+    steps: usize,
+    target: PruningTarget,
+}
+
+pub enum PruningTarget {
+    // One threshold over the weights of all dense layers, so layers end up at different
+    // sparsities.
+    Global(Float),
+    // One sparsity for every dense layer, in layer order.
+    PerLayer(Vec<Float>),
+}
+
+pub struct PruningStep {
+    // Change from the accuracy of the network before pruning.
+    pub accuracy_delta: Float,
+    pub accuracy: Float,
+    pub layer_sparsities: Vec<Float>,
+    pub sparsity: Float,
+    pub step: usize,
+}
+
+impl MagnitudePruning {
+    pub fn new(target: PruningTarget) -> MagnitudePruning {
+        MagnitudePruning {
+            fine_tune_epochs: 0,
+            steps: 1,
+            target,
+        }
+    }
+
+    pub fn with_fine_tune_epochs(mut self, fine_tune_epochs: usize) -> MagnitudePruning {
+        self.fine_tune_epochs = fine_tune_epochs;
+        self
+    }
+
+    pub fn with_steps(mut self, steps: usize) -> MagnitudePruning {
+        assert!(steps > 0, "Pruning needs at least one step");
+        self.steps = steps;
+        self
+    }
+
+    // Fine-tunes on the training data and measures the accuracy on the test data after every
+    // step.
+    pub fn prune(
+        &self,
+        network: &mut NuralNetwork,
+        training_data: &[(Vec<Float>, Vec<Float>)],
+        test_data: &[(Vec<Float>, Vec<Float>)],
+    ) -> Vec<PruningStep> {
+        let initial_accuracy = network.accuracy(test_data);
+
+        (1..=self.steps)
+            .map(|step| {
+                let progress = step as Float / self.steps as Float;
+                match &self.target {
+                    PruningTarget::Global(sparsity) => {
+                        let mut dense_layers = dense_layers(network);
+                        let threshold = threshold(
+                            dense_layers
+                                .iter()
+                                .flat_map(|dense_layer| dense_layer.weights().iter()),
+                            sparsity * progress,
+                        );
+                        if let Some(threshold) = threshold {
+                            dense_layers
+                                .iter_mut()
+                                .for_each(|dense_layer| dense_layer.prune(threshold));
+                        }
+                    }
+                    PruningTarget::PerLayer(sparsities) => {
+                        let mut dense_layers = dense_layers(network);
+                        assert_eq!(
+                            dense_layers.len(),
+                            sparsities.len(),
+                            "Every dense layer needs a sparsity"
+                        );
+                        for (dense_layer, sparsity) in dense_layers.iter_mut().zip(sparsities) {
+                            let threshold =
+                                threshold(dense_layer.weights().iter(), sparsity * progress);
+                            if let Some(threshold) = threshold {
+                                dense_layer.prune(threshold);
+                            }
+                        }
+                    }
+                }
+
+                if self.fine_tune_epochs > 0 {
+                    network.train(training_data, self.fine_tune_epochs);
+                }
+
+                let accuracy = network.accuracy(test_data);
+                let dense_layers = dense_layers(network);
+                let weight_count = dense_layers
+                    .iter()
+                    .map(|dense_layer| dense_layer.weights().len())
+                    .sum::<usize>();
+                let remaining_count = dense_layers
+                    .iter()
+                    .map(|dense_layer| dense_layer.weight_count())
+                    .sum::<usize>();
+                PruningStep {
+                    accuracy_delta: accuracy - initial_accuracy,
+                    accuracy,
+                    layer_sparsities: dense_layers
+                        .iter()
+                        .map(|dense_layer| dense_layer.sparsity())
+                        .collect(),
+                    sparsity: 1.0 - remaining_count as Float / weight_count as Float,
+                    step,
+                }
+            })
+            .collect()
+    }
+}
+
+fn dense_layers(network: &mut NuralNetwork) -> Vec<&mut DenseLayer> {
+    network
+        .layers_mut()
+        .iter_mut()
+        .filter_map(|layer| layer.as_any_mut().downcast_mut::<DenseLayer>())
+        .collect()
+}
+
+// Largest magnitude among the given share of smallest weights, pruning everything up to it
+// reaches the sparsity. Ties may prune a few weights more.
+fn threshold<'a>(weights: impl Iterator<Item = &'a Float>, sparsity: Float) -> Option<Float> {
+    let mut magnitudes = weights.map(|weight| weight.abs()).collect::<Vec<Float>>();
+    let pruned_count =
+        ((sparsity * magnitudes.len() as Float).round() as usize).min(magnitudes.len());
+    if pruned_count == 0 {
+        return None;
+    }
+    magnitudes.sort_by(|a, b| a.total_cmp(b));
+    Some(magnitudes[pruned_count - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    fn network() -> NuralNetwork {
+        NuralNetwork::new(
+            vec![
+                Box::new(DenseLayer::new(4, 8)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+                Box::new(DenseLayer::new(8, 2)),
+            ],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        )
+    }
+
+    fn data() -> Vec<(Vec<Float>, Vec<Float>)> {
+        (0..16)
+            .map(|sample| {
+                let input = (0..4)
+                    .map(|index| ((sample * 4 + index) as Float * 0.37).sin())
+                    .collect::<Vec<Float>>();
+                let output = if input[0] > input[1] {
+                    vec![1.0, 0.0]
+                } else {
+                    vec![0.0, 1.0]
+                };
+                (input, output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn global_pruning_reaches_the_target() {
+        let mut network = network();
+        let steps = MagnitudePruning::new(PruningTarget::Global(0.6))
+            .with_steps(3)
+            .prune(&mut network, &data(), &data());
+        assert_eq!(steps.len(), 3);
+        // 48 weights, 29 of them pruned.
+        assert!((steps[2].sparsity - 29.0 / 48.0).abs() < 1e-12);
+        assert!(steps[0].sparsity < steps[1].sparsity && steps[1].sparsity < steps[2].sparsity);
+    }
+
+    #[test]
+    fn per_layer_pruning_reaches_every_target() {
+        let mut network = network();
+        let steps = MagnitudePruning::new(PruningTarget::PerLayer(vec![0.25, 0.75]))
+            .with_steps(2)
+            .prune(&mut network, &data(), &data());
+        assert_eq!(steps[1].layer_sparsities, vec![0.25, 0.75]);
+        assert_eq!(steps[0].layer_sparsities, vec![0.125, 0.375]);
+    }
+
+    #[test]
+    fn pruned_weights_stay_zero_while_fine_tuning() {
+        let initial_network = serde_cbor::to_vec(&network()).unwrap();
+        let pruned_network = |fine_tune_epochs| {
+            let mut network = serde_cbor::from_slice::<NuralNetwork>(&initial_network).unwrap();
+            MagnitudePruning::new(PruningTarget::Global(0.5))
+                .with_fine_tune_epochs(fine_tune_epochs)
+                .prune(&mut network, &data(), &data());
+            network
+        };
+        let mut pruned = pruned_network(0);
+        let mut fine_tuned = pruned_network(3);
+
+        for (pruned_layer, fine_tuned_layer) in dense_layers(&mut pruned)
+            .into_iter()
+            .zip(dense_layers(&mut fine_tuned))
+        {
+            assert_ne!(pruned_layer.weights(), fine_tuned_layer.weights());
+            for (pruned_weight, fine_tuned_weight) in pruned_layer
+                .weights()
+                .iter()
+                .zip(fine_tuned_layer.weights().iter())
+            {
+                if *pruned_weight == 0.0 {
+                    assert_eq!(*fine_tuned_weight, 0.0);
+                }
+            }
+        }
+    }
+}
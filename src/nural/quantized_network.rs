@@ -0,0 +1,380 @@
+﻿use crate::nural::conv_2d_layer::{Conv2dGeometry, Conv2dLayer};
+use crate::nural::dense_layer::DenseLayer;
+use crate::nural::float::Float;
+use crate::nural::loss_fns::expected_class;
+use crate::nural::nural_network::NuralNetwork;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use ndarray::{Array3, ArrayView3, ArrayView4};
+use serde::{Deserialize, Serialize};
+
+// Inference-only copy of a network with the weights of dense and convolution layers stored as
+// int8, one scale per output channel. The inputs of those layers are quantized with a scale
+// found by calibration, the products are summed as i32 and scaled back, biases stay float.
+// Every other layer is kept as it is.
+#[derive(Deserialize, Serialize)]
+pub struct QuantizedNetwork {
+    layers: Vec<QuantizedNetworkLayer>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum QuantizedNetworkLayer {
+    Conv2d(Conv2dGeometry, QuantizedWeights),
+    Dense(QuantizedWeights),
+    Float(Box<dyn NuralNetworkLayer>),
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct QuantizedWeights {
+    bias: Vec<Float>,
+    input_scale: Float,
+    scales: Vec<Float>,
+    #[serde(with = "int8_bytes")]
+    weights: Vec<i8>,
+}
+
+// Accuracies are the share of samples whose largest output matches the expected class,
+// agreement the share where both models pick the same class.
+pub struct QuantizationReport {
+    pub agreement: Float,
+    pub float_accuracy: Float,
+    pub max_difference: Float,
+    pub quantized_accuracy: Float,
+}
+
+impl QuantizedNetwork {
+    // The calibration inputs should be a representative sample of the training data, the
+    // largest value a layer sees in them sets the scale of its input.
+    pub fn quantize(network: NuralNetwork, calibration_inputs: &[Vec<Float>]) -> QuantizedNetwork {
+        let mut input_ranges = vec![0.0 as Float; network.layers().len()];
+        for input in calibration_inputs {
+            let mut output = input.clone();
+            for (layer_index, layer) in network.layers().iter().enumerate() {
+                input_ranges[layer_index] = output
+                    .iter()
+                    .fold(input_ranges[layer_index], |range, val| range.max(val.abs()));
+                output = layer.forward(&output);
+            }
+        }
+
+        let layers = network
+            .into_layers()
+            .into_iter()
+            .zip(input_ranges)
+            .map(|(layer, input_range)| {
+                if let Some(conv_2d_layer) = layer.as_any().downcast_ref::<Conv2dLayer>() {
+                    QuantizedNetworkLayer::Conv2d(
+                        conv_2d_layer.geometry(),
+                        QuantizedWeights::new(layer.as_ref(), input_range),
+                    )
+                } else if layer.as_any().is::<DenseLayer>() {
+                    QuantizedNetworkLayer::Dense(QuantizedWeights::new(layer.as_ref(), input_range))
+                } else {
+                    QuantizedNetworkLayer::Float(layer)
+                }
+            })
+            .collect();
+        QuantizedNetwork { layers }
+    }
+
+    pub fn compare(
+        &self,
+        network: &NuralNetwork,
+        data: &[(Vec<Float>, Vec<Float>)],
+    ) -> QuantizationReport {
+        let mut agreement = 0;
+        let mut float_correct = 0;
+        let mut max_difference: Float = 0.0;
+        let mut quantized_correct = 0;
+
+        for (input, expected_output) in data.iter() {
+            let float_output = network.predict(input);
+            let quantized_output = self.predict(input);
+            max_difference = float_output.iter().zip(quantized_output.iter()).fold(
+                max_difference,
+                |max_difference, (float_val, quantized_val)| {
+                    max_difference.max((float_val - quantized_val).abs())
+                },
+            );
+
+            let label_class = expected_class(expected_output);
+            let float_class = expected_class(&float_output);
+            let quantized_class = expected_class(&quantized_output);
+            agreement += (float_class == quantized_class) as usize;
+            float_correct += (float_class == label_class) as usize;
+            quantized_correct += (quantized_class == label_class) as usize;
+        }
+
+        QuantizationReport {
+            agreement: agreement as Float / data.len() as Float,
+            float_accuracy: float_correct as Float / data.len() as Float,
+            max_difference,
+            quantized_accuracy: quantized_correct as Float / data.len() as Float,
+        }
+    }
+
+    pub fn load_file(file_path: &str) -> Result<QuantizedNetwork, std::io::Error> {
+        let serialized_bytes = std::fs::read(file_path)?;
+        serde_cbor::from_slice(&serialized_bytes).map_err(std::io::Error::other)
+    }
+
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        self.layers
+            .iter()
+            .fold(input.to_vec(), |output, layer| layer.forward(&output))
+    }
+
+    pub fn save_file(&self, file_path: &str) -> Result<(), std::io::Error> {
+        let serialized_bytes = serde_cbor::to_vec(self).map_err(std::io::Error::other)?;
+        std::fs::write(file_path, serialized_bytes)
+    }
+}
+
+impl QuantizedNetworkLayer {
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        match self {
+            QuantizedNetworkLayer::Conv2d(geometry, weights) => {
+                let quantized_input = weights.quantize_input(input);
+                let input = ArrayView3::from_shape(geometry.input_shape, &quantized_input)
+                    .expect("Conv2dLayer input size mismatch");
+                let kernel =
+                    ArrayView4::from_shape(geometry.kernel_shape, &weights.weights).unwrap();
+
+                let (output_height, output_width) = geometry.output_size();
+                let mut sums =
+                    Array3::<i32>::zeros((geometry.kernel_shape[0], output_height, output_width));
+                geometry.for_each_tap(|input_index, output_index, kernel_index| {
+                    sums[output_index] += input[input_index] as i32 * kernel[kernel_index] as i32;
+                });
+
+                sums.indexed_iter()
+                    .map(|((filter, _, _), &sum)| weights.dequantize(filter, sum))
+                    .collect()
+            }
+            QuantizedNetworkLayer::Dense(weights) => {
+                assert_eq!(
+                    input.len() * weights.bias.len(),
+                    weights.weights.len(),
+                    "DenseLayer input size mismatch"
+                );
+                let quantized_input = weights.quantize_input(input);
+                weights
+                    .weights
+                    .chunks(quantized_input.len())
+                    .enumerate()
+                    .map(|(output, output_weights)| {
+                        let sum = output_weights
+                            .iter()
+                            .zip(quantized_input.iter())
+                            .map(|(&weight, &input_val)| weight as i32 * input_val as i32)
+                            .sum();
+                        weights.dequantize(output, sum)
+                    })
+                    .collect()
+            }
+            QuantizedNetworkLayer::Float(layer) => layer.forward(input),
+        }
+    }
+}
+
+impl QuantizedWeights {
+    // Symmetric quantization, the largest weight of every output channel maps to 127 and the
+    // largest calibration input to 127.
+    fn new(layer: &dyn NuralNetworkLayer, input_range: Float) -> QuantizedWeights {
+        let parameters = layer.parameters();
+        let bias = parameters[0].iter().copied().collect::<Vec<Float>>();
+        let weights = parameters[1].iter().copied().collect::<Vec<Float>>();
+        let channel_size = weights.len() / bias.len();
+
+        let scales = weights
+            .chunks(channel_size)
+            .map(|channel| scale(channel.iter().fold(0.0, |range, val| range.max(val.abs()))))
+            .collect::<Vec<Float>>();
+        let weights = weights
+            .chunks(channel_size)
+            .zip(scales.iter())
+            .flat_map(|(channel, &scale)| {
+                channel.iter().map(move |val| (val / scale).round() as i8)
+            })
+            .collect();
+
+        QuantizedWeights {
+            bias,
+            input_scale: scale(input_range),
+            scales,
+            weights,
+        }
+    }
+
+    fn dequantize(&self, channel: usize, sum: i32) -> Float {
+        sum as Float * self.input_scale * self.scales[channel] + self.bias[channel]
+    }
+
+    // Inputs beyond the calibrated range saturate.
+    fn quantize_input(&self, input: &[Float]) -> Vec<i8> {
+        input
+            .iter()
+            .map(|val| (val / self.input_scale).round().clamp(-127.0, 127.0) as i8)
+            .collect()
+    }
+}
+
+fn scale(range: Float) -> Float {
+    if range > 0.0 {
+        range / 127.0
+    } else {
+        1.0
+    }
+}
+
+// Stores the int8 weights as one byte string instead of a list of numbers.
+mod int8_bytes {
+    use serde::de::{SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S>(values: &[i8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let bytes = values.iter().map(|&val| val as u8).collect::<Vec<u8>>();
+        serializer.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'a, D>(deserializer: D) -> Result<Vec<i8>, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        struct BytesVisitor;
+        impl<'a> Visitor<'a> for BytesVisitor {
+            type Value = Vec<i8>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("int8 bytes")
+            }
+
+            fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                Ok(bytes.iter().map(|&byte| byte as i8).collect())
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'a>,
+            {
+                let mut values = vec![];
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    values.push(byte as i8);
+                }
+                Ok(values)
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+    use crate::nural::nural_network::NuralNetworkLossKind;
+
+    // Fixed parameters, so the quantization error does not depend on the random initialization.
+    fn network() -> NuralNetwork {
+        let mut network = NuralNetwork::new(
+            vec![
+                Box::new(Conv2dLayer::new([1, 6, 6], 3, 3)),
+                Box::new(ActivationLayer::new(ActivationLayerKind::ReLu)),
+                Box::new(DenseLayer::new(3 * 4 * 4, 4)),
+            ],
+            0.05,
+            NuralNetworkLossKind::Mse,
+        );
+        for layer in network.layers_mut() {
+            for mut parameter in layer.parameters_mut() {
+                parameter
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(index, val)| *val = (index as Float * 0.53).sin());
+            }
+        }
+        network
+    }
+
+    fn inputs() -> Vec<Vec<Float>> {
+        (0..8)
+            .map(|sample| {
+                (0..36)
+                    .map(|index| ((sample * 36 + index) as Float * 0.37).sin())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quantized_predictions_stay_close() {
+        let network = network();
+        let quantized_network = QuantizedNetwork::quantize(self::network(), &inputs());
+        // Labelled with the float predictions, so the float network is always right.
+        let data = inputs()
+            .into_iter()
+            .map(|input| {
+                let output = network.predict(&input);
+                (input, output)
+            })
+            .collect::<Vec<_>>();
+        let output_range = data
+            .iter()
+            .flat_map(|(_, output)| output.iter())
+            .fold(0.0 as Float, |range, val| range.max(val.abs()));
+
+        let report = quantized_network.compare(&network, &data);
+        assert!(
+            report.max_difference < 0.01 * output_range,
+            "max difference {}, output range {}",
+            report.max_difference,
+            output_range
+        );
+        assert_eq!(report.float_accuracy, 1.0);
+        assert_eq!(report.agreement, 1.0);
+        assert_eq!(report.quantized_accuracy, 1.0);
+    }
+
+    #[test]
+    fn save_and_load_keep_the_int8_weights() {
+        let quantized_network = QuantizedNetwork::quantize(network(), &inputs());
+        let file_path =
+            std::env::temp_dir().join(format!("nural_quantized_{}", std::process::id()));
+        let file_path = file_path.to_str().unwrap();
+        quantized_network.save_file(file_path).unwrap();
+        let loaded_network = QuantizedNetwork::load_file(file_path).unwrap();
+        std::fs::remove_file(file_path).unwrap();
+
+        for (layer, loaded_layer) in quantized_network
+            .layers
+            .iter()
+            .zip(loaded_network.layers.iter())
+        {
+            match (layer, loaded_layer) {
+                (
+                    QuantizedNetworkLayer::Conv2d(_, weights),
+                    QuantizedNetworkLayer::Conv2d(_, loaded_weights),
+                )
+                | (
+                    QuantizedNetworkLayer::Dense(weights),
+                    QuantizedNetworkLayer::Dense(loaded_weights),
+                ) => {
+                    assert_eq!(weights.weights, loaded_weights.weights);
+                    assert_eq!(weights.scales, loaded_weights.scales);
+                }
+                (QuantizedNetworkLayer::Float(_), QuantizedNetworkLayer::Float(_)) => {}
+                _ => panic!("The loaded layers differ"),
+            }
+        }
+        for input in inputs() {
+            assert_eq!(
+                quantized_network.predict(&input),
+                loaded_network.predict(&input)
+            );
+        }
+    }
+}
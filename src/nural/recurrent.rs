@@ -0,0 +1,138 @@
+﻿use crate::nural::float::Float;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use rand::Rng;
+
+// Sequence layers receive the whole sequence as one flat buffer of `steps * inputs` values,
+// one row per time step, the same row-major layout the digit images already use.
+pub fn sequence(input: &[Float], inputs: usize) -> Vec<ArrayView1<'_, Float>> {
+    assert_eq!(
+        input.len() % inputs,
+        0,
+        "Sequence length must be a multiple of the step size"
+    );
+
+    input.chunks(inputs).map(ArrayView1::from).collect()
+}
+
+pub fn sequence_output_shape(
+    input_shape: &[usize],
+    inputs: usize,
+    outputs: usize,
+    return_sequences: bool,
+) -> Result<Vec<usize>, String> {
+    match input_shape {
+        [0, _] => Err("Sequence layer expects at least one time step".to_string()),
+        [steps, step_inputs] if *step_inputs == inputs => Ok(if return_sequences {
+            vec![*steps, outputs]
+        } else {
+            vec![outputs]
+        }),
+        _ => Err(format!(
+            "Sequence layer expects input shape [steps, {}], got {:?}",
+            inputs, input_shape
+        )),
+    }
+}
+
+pub fn outer(col: &Array1<Float>, row: &ArrayView1<Float>) -> Array2<Float> {
+    col.view()
+        .insert_axis(Axis(1))
+        .dot(&row.view().insert_axis(Axis(0)))
+}
+
+// The states start with the initial zero state, which is what an empty sequence returns
+// when only the last state is wanted.
+pub fn collect_output(states: &[Array1<Float>], return_sequences: bool) -> Vec<Float> {
+    if return_sequences {
+        states[1..]
+            .iter()
+            .flat_map(|state| state.iter().copied())
+            .collect()
+    } else {
+        states.last().unwrap().to_vec()
+    }
+}
+
+// Spreads the incoming gradient over the time steps, when only the last state is returned
+// the earlier steps receive no direct gradient.
+pub fn step_gradients(
+    output_gradient: &[Float],
+    steps: usize,
+    outputs: usize,
+    return_sequences: bool,
+) -> Vec<Array1<Float>> {
+    if return_sequences {
+        output_gradient
+            .chunks(outputs)
+            .map(|gradient| Array1::from(gradient.to_vec()))
+            .collect()
+    } else {
+        let mut gradients = vec![Array1::zeros(outputs); steps];
+        if let Some(last) = gradients.last_mut() {
+            *last = Array1::from(output_gradient.to_vec());
+        }
+        gradients
+    }
+}
+
+// Truncated BPTT splits the sequence into windows of `truncation` steps and stops the
+// gradient from flowing through the hidden state into the previous window.
+pub fn is_truncated(step: usize, truncation: Option<usize>) -> bool {
+    match truncation {
+        Some(truncation) => step.is_multiple_of(truncation),
+        None => false,
+    }
+}
+
+pub fn init_weights(rows: usize, cols: usize, hidden: usize) -> Array2<Float> {
+    let mut rng = rand::rng();
+    let limit = 1.0 / (hidden as Float).sqrt();
+    Array2::from_shape_fn((rows, cols), |_| rng.random_range(-limit..limit))
+}
+
+pub fn init_bias(rows: usize, hidden: usize) -> Array1<Float> {
+    let mut rng = rand::rng();
+    let limit = 1.0 / (hidden as Float).sqrt();
+    Array1::from_shape_fn(rows, |_| rng.random_range(-limit..limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gru_layer::GruLayer;
+    use crate::nural::lstm_layer::LstmLayer;
+    use crate::nural::nural_network_layer::NuralNetworkLayer;
+    use crate::nural::rnn_layer::RnnLayer;
+
+    #[test]
+    fn truncation_stops_the_gradient_of_earlier_steps() {
+        let (steps, inputs, outputs, truncation) = (10, 3, 4, 3);
+        let input = (0..steps * inputs)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        let layers: Vec<Box<dyn NuralNetworkLayer>> = vec![
+            Box::new(RnnLayer::new(inputs, outputs).with_truncation(truncation)),
+            Box::new(LstmLayer::new(inputs, outputs).with_truncation(truncation)),
+            Box::new(GruLayer::new(inputs, outputs).with_truncation(truncation)),
+        ];
+        for layer in layers {
+            let output = layer.forward(&input);
+            let (input_gradient, _) = layer.gradient(&input, &output, &vec![1.0; outputs]);
+            for (step, step_gradient) in input_gradient.chunks(inputs).enumerate() {
+                if step + truncation < steps - 1 {
+                    assert!(
+                        step_gradient
+                            .iter()
+                            .all(|&gradient_val| gradient_val == 0.0),
+                        "{} step {} has a gradient",
+                        layer.name(),
+                        step
+                    );
+                }
+            }
+            assert!(input_gradient[(steps - 1) * inputs..]
+                .iter()
+                .any(|&gradient_val| gradient_val != 0.0));
+        }
+    }
+}
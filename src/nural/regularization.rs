@@ -0,0 +1,54 @@
+﻿use crate::nural::float::Float;
+use ndarray::{Array2, Axis};
+use serde::{Deserialize, Serialize};
+
+// Weight penalties and constraints of a layer. L1 and L2 add to the loss, decoupled weight
+// decay shrinks the weights directly after each update and max-norm caps the norm of the
+// incoming weights of every unit. Biases are left alone unless `include_bias` is set.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Regularization {
+    pub include_bias: bool,
+    pub l1: Float,
+    pub l2: Float,
+    pub max_norm: Option<Float>,
+    pub weight_decay: Float,
+}
+
+impl Regularization {
+    // Rescales every row (the incoming weights of one unit) whose norm is over the limit.
+    pub fn constrain(&self, weights: &mut Array2<Float>) {
+        if let Some(max_norm) = self.max_norm {
+            for mut row in weights.axis_iter_mut(Axis(0)) {
+                let norm = row.iter().map(|value| value * value).sum::<Float>().sqrt();
+                if norm > max_norm {
+                    row *= max_norm / norm;
+                }
+            }
+        }
+    }
+
+    // Decoupled weight decay, applied after the gradient step and not part of the loss.
+    pub fn decay(&self, values: &mut Array2<Float>, learning_rate: Float) {
+        if self.weight_decay != 0.0 {
+            *values *= 1.0 - learning_rate * self.weight_decay;
+        }
+    }
+
+    // Gradient of the penalty, added to the loss gradient before the update.
+    pub fn gradient(&self, values: &Array2<Float>) -> Array2<Float> {
+        values.mapv(|value| {
+            let l1_gradient = if value == 0.0 {
+                0.0
+            } else {
+                self.l1 * value.signum()
+            };
+            l1_gradient + self.l2 * value
+        })
+    }
+
+    pub fn penalty(&self, values: &Array2<Float>) -> Float {
+        values.iter().fold(0.0, |penalty, &value| {
+            penalty + self.l1 * value.abs() + 0.5 * self.l2 * value * value
+        })
+    }
+}
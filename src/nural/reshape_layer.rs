@@ -0,0 +1,63 @@
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+#[derive(Deserialize, Serialize)]
+pub struct ReshapeLayer {
+    shape: Vec<usize>,
+}
+
+impl ReshapeLayer {
+    pub fn new(shape: Vec<usize>) -> ReshapeLayer {
+        ReshapeLayer { shape }
+    }
+}
+
+impl NuralNetworkLayer for ReshapeLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        input.to_vec()
+    }
+
+    fn gradient(
+        &self,
+        _input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        (output_gradient.to_vec(), ParameterGradient::default())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        if input_shape.iter().product::<usize>() == self.shape.iter().product::<usize>() {
+            Ok(self.shape.clone())
+        } else {
+            Err(format!(
+                "ReshapeLayer cannot reshape {:?} into {:?}",
+                input_shape, self.shape
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_shape_keeps_the_size() {
+        let layer = ReshapeLayer::new(vec![4, 6]);
+        assert_eq!(layer.output_shape(&[24]), Ok(vec![4, 6]));
+        assert_eq!(layer.output_shape(&[2, 12]), Ok(vec![4, 6]));
+        assert!(layer.output_shape(&[25]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+﻿use crate::nural::activation_fns::TANH;
+use crate::nural::array_serde;
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::recurrent::*;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array1, Array2, ArrayViewD, ArrayViewMutD};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+#[derive(Deserialize, Serialize)]
+pub struct RnnLayer {
+    #[serde(with = "array_serde")]
+    bias: Array1<Float>,
+    #[serde(with = "array_serde")]
+    hidden_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    input_weights: Array2<Float>,
+    return_sequences: bool,
+    truncation: Option<usize>,
+}
+
+impl RnnLayer {
+    pub fn new(inputs: usize, outputs: usize) -> RnnLayer {
+        RnnLayer {
+            bias: init_bias(outputs, outputs),
+            hidden_weights: init_weights(outputs, outputs, outputs),
+            input_weights: init_weights(outputs, inputs, outputs),
+            return_sequences: false,
+            truncation: None,
+        }
+    }
+
+    pub fn with_return_sequences(mut self, return_sequences: bool) -> RnnLayer {
+        self.return_sequences = return_sequences;
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: usize) -> RnnLayer {
+        self.truncation = Some(truncation);
+        self
+    }
+
+    fn forward_states(&self, input: &[Float]) -> Vec<Array1<Float>> {
+        let mut states = vec![Array1::zeros(self.bias.len())];
+        for step_input in sequence(input, self.input_weights.ncols()) {
+            let state = (self.input_weights.dot(&step_input)
+                + self.hidden_weights.dot(states.last().unwrap())
+                + &self.bias)
+                .mapv(TANH.fx);
+            states.push(state);
+        }
+        states
+    }
+}
+
+impl NuralNetworkLayer for RnnLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // The matrix products and bias adds of every step, the state updates are left out.
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let steps = input_shape.first().copied().unwrap_or(1);
+        steps * (2 * (self.input_weights.len() + self.hidden_weights.len()) + self.bias.len())
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let states = self.forward_states(input);
+        collect_output(&states, self.return_sequences)
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let steps = sequence(input, self.input_weights.ncols());
+        let states = self.forward_states(input);
+        let step_gradients = step_gradients(
+            output_gradient,
+            steps.len(),
+            self.bias.len(),
+            self.return_sequences,
+        );
+
+        let mut bias_gradient = Array1::zeros(self.bias.raw_dim());
+        let mut hidden_weights_gradient = Array2::zeros(self.hidden_weights.raw_dim());
+        let mut input_weights_gradient = Array2::zeros(self.input_weights.raw_dim());
+        let mut input_gradient = vec![Array1::zeros(self.input_weights.ncols()); steps.len()];
+
+        let mut state_gradient = Array1::<Float>::zeros(self.bias.len());
+        for step in (0..steps.len()).rev() {
+            state_gradient += &step_gradients[step];
+
+            let pre_activation_gradient = &state_gradient * &states[step + 1].mapv(|h| 1.0 - h * h);
+
+            bias_gradient += &pre_activation_gradient;
+            hidden_weights_gradient += &outer(&pre_activation_gradient, &states[step].view());
+            input_weights_gradient += &outer(&pre_activation_gradient, &steps[step]);
+            input_gradient[step] = self.input_weights.t().dot(&pre_activation_gradient);
+
+            state_gradient = if is_truncated(step, self.truncation) {
+                Array1::zeros(self.bias.len())
+            } else {
+                self.hidden_weights.t().dot(&pre_activation_gradient)
+            };
+        }
+
+        let parameter_gradient = bias_gradient
+            .iter()
+            .chain(hidden_weights_gradient.iter())
+            .chain(input_weights_gradient.iter())
+            .copied()
+            .collect();
+        (
+            input_gradient.into_iter().flatten().collect(),
+            parameter_gradient,
+        )
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        sequence_output_shape(
+            input_shape,
+            self.input_weights.ncols(),
+            self.bias.len(),
+            self.return_sequences,
+        )
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        vec![
+            self.bias.view().into_dyn(),
+            self.hidden_weights.view().into_dyn(),
+            self.input_weights.view().into_dyn(),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        vec![
+            self.bias.view_mut().into_dyn(),
+            self.hidden_weights.view_mut().into_dyn(),
+            self.input_weights.view_mut().into_dyn(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let input = (0..4 * 3)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        for return_sequences in [false, true] {
+            let mut layer = RnnLayer::new(3, 5).with_return_sequences(return_sequences);
+            let check = gradient_check(&mut layer, &input);
+            assert!(
+                check.passed(),
+                "input error {}, parameter error {}",
+                check.input_error,
+                check.parameter_error
+            );
+        }
+    }
+
+    #[test]
+    fn empty_sequence_returns_initial_state() {
+        let layer = RnnLayer::new(3, 5);
+        assert!(layer.output_shape(&[0, 3]).is_err());
+        assert_eq!(layer.forward(&[]), vec![0.0; 5]);
+        assert_eq!(
+            layer.gradient(&[], &[0.0; 5], &[1.0; 5]).0,
+            Vec::<Float>::new()
+        );
+    }
+}
@@ -1,5 +1,6 @@
-﻿use crate::nural::nural_network_layer::NuralNetworkLayer;
-use ndarray::Array2;
+﻿use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
 use serde::{Deserialize, Serialize};
 use std::any::Any;
 
@@ -17,25 +18,74 @@ impl NuralNetworkLayer for SoftmaxLayer {
         self
     }
 
-    fn backward(
-        &mut self,
-        _input: &[f64],
-        output: &[f64],
-        output_gradient: &[f64],
-        _learning_rate: f64,
-    ) -> Vec<f64> {
-        let output_vec = Array2::from_shape_vec((output.len(), 1), output.to_vec()).unwrap();
-        let output_gradient_vec = Array2::from_shape_vec((output_gradient.len(), 1), output_gradient.to_vec()).unwrap();
-        let identity = Array2::ones((output.len(), 1));
-        let (output, _) = ((identity - output_vec.t()) * output_vec).dot(&output_gradient_vec).into_raw_vec_and_offset();
-        output
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // Exponent, sum and division per element.
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        3 * input_shape.iter().product::<usize>()
     }
 
-    fn forward(&self, input: &[f64]) -> Vec<f64> {
-        let exp_sum = input.iter().map(|val| (*val).exp()).sum::<f64>();
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let exp_sum = input.iter().map(|val| (*val).exp()).sum::<Float>();
         input
             .iter()
             .map(|val| (*val).exp() / exp_sum)
-            .collect::<Vec<f64>>()
+            .collect::<Vec<Float>>()
+    }
+
+    // The softmax Jacobian is diag(y) - y y^T, so the input gradient is y * (g - y.g).
+    fn gradient(
+        &self,
+        _input: &[Float],
+        output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let weighted_gradient = output
+            .iter()
+            .zip(output_gradient.iter())
+            .map(|(output_val, gradient_val)| output_val * gradient_val)
+            .sum::<Float>();
+        let input_gradient = output
+            .iter()
+            .zip(output_gradient.iter())
+            .map(|(output_val, gradient_val)| output_val * (gradient_val - weighted_gradient))
+            .collect();
+        (input_gradient, ParameterGradient::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+    use crate::nural::loss_fns::CROSS_ENTROPY;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = SoftmaxLayer::new();
+        let check = gradient_check(&mut layer, &[0.4, -1.2, 2.0, 0.1]);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+
+    #[test]
+    fn cross_entropy_gradient_through_softmax_is_output_minus_label() {
+        let layer = SoftmaxLayer::new();
+        let input = [0.4, -1.2, 2.0, 0.1];
+        let label = [0.0, 0.0, 1.0, 0.0];
+        let output = layer.forward(&input);
+        let (input_gradient, _) =
+            layer.gradient(&input, &output, &(CROSS_ENTROPY.dx)(&output, &label));
+        for ((gradient_val, output_val), label_val) in
+            input_gradient.iter().zip(output.iter()).zip(label.iter())
+        {
+            assert!((gradient_val - (output_val - label_val)).abs() < 1e-12);
+        }
     }
 }
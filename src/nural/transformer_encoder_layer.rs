@@ -0,0 +1,242 @@
+use crate::nural::array_serde;
+use crate::nural::attention_layer::AttentionLayer;
+use crate::nural::float::Float;
+use crate::nural::layer_norm_layer::LayerNormLayer;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use ndarray::{Array1, Array2, ArrayViewD, ArrayViewMutD, Axis};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Post-norm transformer encoder block over a `[steps, features]` sequence:
+// x = norm(x + attention(x)), then x = norm(x + feed_forward(x)) with the feed forward
+// network applied to every step on its own.
+#[derive(Deserialize, Serialize)]
+pub struct TransformerEncoderLayer {
+    attention: AttentionLayer,
+    attention_norm: LayerNormLayer,
+    feed_forward_norm: LayerNormLayer,
+    #[serde(with = "array_serde")]
+    hidden_bias: Array1<Float>,
+    #[serde(with = "array_serde")]
+    hidden_weights: Array2<Float>,
+    #[serde(with = "array_serde")]
+    output_bias: Array1<Float>,
+    #[serde(with = "array_serde")]
+    output_weights: Array2<Float>,
+}
+
+struct TransformerEncoderState {
+    attended: Vec<Float>,
+    attention_sum: Vec<Float>,
+    feed_forward_sum: Vec<Float>,
+    hidden: Array2<Float>,
+    normalized: Vec<Float>,
+    output: Vec<Float>,
+}
+
+impl TransformerEncoderLayer {
+    pub fn new(features: usize, heads: usize, hidden: usize) -> TransformerEncoderLayer {
+        let mut rng = rand::rng();
+        let hidden_limit = 1.0 / (features as Float).sqrt();
+        let output_limit = 1.0 / (hidden as Float).sqrt();
+        TransformerEncoderLayer {
+            attention: AttentionLayer::new(features, heads),
+            attention_norm: LayerNormLayer::new(features),
+            feed_forward_norm: LayerNormLayer::new(features),
+            hidden_bias: Array1::zeros(hidden),
+            hidden_weights: Array2::from_shape_fn((hidden, features), |_| {
+                rng.random_range(-hidden_limit..hidden_limit)
+            }),
+            output_bias: Array1::zeros(features),
+            output_weights: Array2::from_shape_fn((features, hidden), |_| {
+                rng.random_range(-output_limit..output_limit)
+            }),
+        }
+    }
+
+    fn features(&self) -> usize {
+        self.output_bias.len()
+    }
+
+    fn forward_state(&self, input: &[Float]) -> TransformerEncoderState {
+        let attended = self.attention.forward(input);
+        let attention_sum = add(input, &attended);
+        let normalized = self.attention_norm.forward(&attention_sum);
+
+        let normalized_mx = self.steps(&normalized);
+        let hidden = normalized_mx.dot(&self.hidden_weights.t()) + &self.hidden_bias;
+        let feed_forward = hidden
+            .mapv(|val| val.max(0.0))
+            .dot(&self.output_weights.t())
+            + &self.output_bias;
+        let feed_forward_sum = add(
+            &normalized,
+            &feed_forward.iter().copied().collect::<Vec<_>>(),
+        );
+        let output = self.feed_forward_norm.forward(&feed_forward_sum);
+
+        TransformerEncoderState {
+            attended,
+            attention_sum,
+            feed_forward_sum,
+            hidden,
+            normalized,
+            output,
+        }
+    }
+
+    fn steps(&self, values: &[Float]) -> Array2<Float> {
+        Array2::from_shape_vec(
+            (values.len() / self.features(), self.features()),
+            values.to_vec(),
+        )
+        .expect("TransformerEncoderLayer input must be a [steps, features] sequence")
+    }
+}
+
+impl NuralNetworkLayer for TransformerEncoderLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let steps = input_shape.first().copied().unwrap_or(1);
+        self.attention.flops(input_shape)
+            + self.attention_norm.flops(input_shape)
+            + self.feed_forward_norm.flops(input_shape)
+            + steps
+                * (2 * (self.hidden_weights.len() + self.output_weights.len())
+                    + self.hidden_bias.len()
+                    + self.output_bias.len())
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        self.forward_state(input).output
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let state = self.forward_state(input);
+
+        let (feed_forward_sum_gradient, feed_forward_norm_gradient) = self
+            .feed_forward_norm
+            .gradient(&state.feed_forward_sum, &state.output, output_gradient);
+
+        let feed_forward_gradient = self.steps(&feed_forward_sum_gradient);
+        let activated = state.hidden.mapv(|val| val.max(0.0));
+        let hidden_gradient = feed_forward_gradient.dot(&self.output_weights)
+            * state.hidden.mapv(|val| if val > 0.0 { 1.0 } else { 0.0 });
+        let normalized_gradient = hidden_gradient.dot(&self.hidden_weights);
+
+        let hidden_bias_gradient = hidden_gradient.sum_axis(Axis(0));
+        let hidden_weights_gradient = hidden_gradient.t().dot(&self.steps(&state.normalized));
+        let output_bias_gradient = feed_forward_gradient.sum_axis(Axis(0));
+        let output_weights_gradient = feed_forward_gradient.t().dot(&activated);
+
+        let normalized_gradient = add(
+            &feed_forward_sum_gradient,
+            &normalized_gradient.iter().copied().collect::<Vec<_>>(),
+        );
+        let (attention_sum_gradient, attention_norm_gradient) = self.attention_norm.gradient(
+            &state.attention_sum,
+            &state.normalized,
+            &normalized_gradient,
+        );
+        let (attention_gradient, attention_parameter_gradient) =
+            self.attention
+                .gradient(input, &state.attended, &attention_sum_gradient);
+
+        // The attention and norm layers always return dense gradients.
+        let parameter_gradient = attention_parameter_gradient
+            .values()
+            .chain(attention_norm_gradient.values())
+            .chain(feed_forward_norm_gradient.values())
+            .chain(hidden_bias_gradient.iter())
+            .chain(hidden_weights_gradient.iter())
+            .chain(output_bias_gradient.iter())
+            .chain(output_weights_gradient.iter())
+            .copied()
+            .collect();
+        (
+            add(&attention_sum_gradient, &attention_gradient),
+            parameter_gradient,
+        )
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        match input_shape {
+            [_, features] if *features == self.features() => Ok(input_shape.to_vec()),
+            _ => Err(format!(
+                "TransformerEncoderLayer expects input shape [steps, {}], got {:?}",
+                self.features(),
+                input_shape
+            )),
+        }
+    }
+
+    // The attention and norm parameters come first, in the order of their own layers.
+    fn parameters(&self) -> Vec<ArrayViewD<'_, Float>> {
+        let mut parameters = self.attention.parameters();
+        parameters.extend(self.attention_norm.parameters());
+        parameters.extend(self.feed_forward_norm.parameters());
+        parameters.extend([
+            self.hidden_bias.view().into_dyn(),
+            self.hidden_weights.view().into_dyn(),
+            self.output_bias.view().into_dyn(),
+            self.output_weights.view().into_dyn(),
+        ]);
+        parameters
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, Float>> {
+        let mut parameters = self.attention.parameters_mut();
+        parameters.extend(self.attention_norm.parameters_mut());
+        parameters.extend(self.feed_forward_norm.parameters_mut());
+        parameters.extend([
+            self.hidden_bias.view_mut().into_dyn(),
+            self.hidden_weights.view_mut().into_dyn(),
+            self.output_bias.view_mut().into_dyn(),
+            self.output_weights.view_mut().into_dyn(),
+        ]);
+        parameters
+    }
+}
+
+fn add(values: &[Float], other: &[Float]) -> Vec<Float> {
+    values
+        .iter()
+        .zip(other.iter())
+        .map(|(val, other_val)| val + other_val)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut layer = TransformerEncoderLayer::new(8, 2, 16);
+        let input = (0..3 * 8)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        let check = gradient_check(&mut layer, &input);
+        assert!(
+            check.passed(),
+            "input error {}, parameter error {}",
+            check.input_error,
+            check.parameter_error
+        );
+    }
+}
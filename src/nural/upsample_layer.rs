@@ -0,0 +1,170 @@
+use crate::nural::float::Float;
+use crate::nural::nural_network_layer::NuralNetworkLayer;
+use crate::nural::parameter_gradient::ParameterGradient;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+// Scales every channel of a flat `[channels, height, width]` image by a whole factor.
+#[derive(Deserialize, Serialize)]
+pub struct UpsampleLayer {
+    input_shape: [usize; 3],
+    kind: UpsampleLayerKind,
+    scale: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum UpsampleLayerKind {
+    Bilinear,
+    Nearest,
+}
+
+impl UpsampleLayer {
+    pub fn new(input_shape: [usize; 3], scale: usize, kind: UpsampleLayerKind) -> UpsampleLayer {
+        UpsampleLayer {
+            input_shape,
+            kind,
+            scale,
+        }
+    }
+
+    // The input pixels an output pixel reads from, with their weights.
+    fn taps(&self, output_row: usize, output_col: usize) -> Vec<(usize, usize, Float)> {
+        match self.kind {
+            UpsampleLayerKind::Nearest => {
+                vec![(output_row / self.scale, output_col / self.scale, 1.0)]
+            }
+            UpsampleLayerKind::Bilinear => {
+                let (row, next_row, row_weight) =
+                    bilinear_source(output_row, self.scale, self.input_shape[1]);
+                let (col, next_col, col_weight) =
+                    bilinear_source(output_col, self.scale, self.input_shape[2]);
+                vec![
+                    (row, col, (1.0 - row_weight) * (1.0 - col_weight)),
+                    (row, next_col, (1.0 - row_weight) * col_weight),
+                    (next_row, col, row_weight * (1.0 - col_weight)),
+                    (next_row, next_col, row_weight * col_weight),
+                ]
+            }
+        }
+    }
+}
+
+impl NuralNetworkLayer for UpsampleLayer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // Nearest neighbour only copies, bilinear blends four inputs per output.
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        let [channels, height, width] = self.input_shape;
+        match self.kind {
+            UpsampleLayerKind::Bilinear => 8 * channels * height * width * self.scale * self.scale,
+            UpsampleLayerKind::Nearest => 0,
+        }
+    }
+
+    fn forward(&self, input: &[Float]) -> Vec<Float> {
+        let [channels, height, width] = self.input_shape;
+        assert_eq!(
+            input.len(),
+            channels * height * width,
+            "UpsampleLayer input size mismatch"
+        );
+
+        let mut output = Vec::with_capacity(input.len() * self.scale * self.scale);
+        for channel in 0..channels {
+            for output_row in 0..height * self.scale {
+                for output_col in 0..width * self.scale {
+                    output.push(
+                        self.taps(output_row, output_col)
+                            .iter()
+                            .map(|&(row, col, weight)| {
+                                weight * input[(channel * height + row) * width + col]
+                            })
+                            .sum(),
+                    );
+                }
+            }
+        }
+        output
+    }
+
+    fn gradient(
+        &self,
+        input: &[Float],
+        _output: &[Float],
+        output_gradient: &[Float],
+    ) -> (Vec<Float>, ParameterGradient) {
+        let [channels, height, width] = self.input_shape;
+        let (output_height, output_width) = (height * self.scale, width * self.scale);
+
+        let mut input_gradient = vec![0.0; input.len()];
+        for channel in 0..channels {
+            for output_row in 0..output_height {
+                for output_col in 0..output_width {
+                    let gradient = output_gradient
+                        [(channel * output_height + output_row) * output_width + output_col];
+                    for (row, col, weight) in self.taps(output_row, output_col) {
+                        input_gradient[(channel * height + row) * width + col] += weight * gradient;
+                    }
+                }
+            }
+        }
+        (input_gradient, ParameterGradient::default())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, String> {
+        let [channels, height, width] = self.input_shape;
+        if input_shape == self.input_shape {
+            Ok(vec![channels, height * self.scale, width * self.scale])
+        } else {
+            Err(format!(
+                "UpsampleLayer expects input shape {:?}, got {:?}",
+                self.input_shape, input_shape
+            ))
+        }
+    }
+}
+
+// Maps an output coordinate to the two neighbouring input coordinates and the weight of the
+// second one, pixel centers are aligned and the edges are clamped.
+fn bilinear_source(output_index: usize, scale: usize, size: usize) -> (usize, usize, Float) {
+    let source = ((output_index as Float + 0.5) / scale as Float - 0.5).max(0.0);
+    let index = (source.floor() as usize).min(size - 1);
+    let next_index = (index + 1).min(size - 1);
+    (index, next_index, source - index as Float)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nural::gradient_check::gradient_check;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let input = (0..2 * 3 * 4)
+            .map(|index| (index as Float * 0.37).sin())
+            .collect::<Vec<Float>>();
+        for kind in [UpsampleLayerKind::Nearest, UpsampleLayerKind::Bilinear] {
+            let mut layer = UpsampleLayer::new([2, 3, 4], 2, kind);
+            let check = gradient_check(&mut layer, &input);
+            assert!(
+                check.passed(),
+                "input error {}, parameter error {}",
+                check.input_error,
+                check.parameter_error
+            );
+        }
+    }
+
+    #[test]
+    fn output_shape_scales_height_and_width() {
+        let layer = UpsampleLayer::new([2, 3, 4], 3, UpsampleLayerKind::Nearest);
+        assert_eq!(layer.output_shape(&[2, 3, 4]), Ok(vec![2, 9, 12]));
+        assert!(layer.output_shape(&[2, 4, 3]).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+﻿use crate::digits_network::{digit_samples, get_all_digits, print_predictions, DIGIT_SIZE};
+use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::dense_layer::DenseLayer;
+use crate::nural::lstm_layer::LstmLayer;
+use crate::nural::nural_network::{NuralNetwork, NuralNetworkLossKind};
+use crate::nural::reshape_layer::ReshapeLayer;
+use crate::utils::shuffle_iter::ShuffleIterExt;
+
+// Reads every digit as a sequence of 28 rows of 28 pixels.
+pub fn rnn_digit_network() {
+    let digits = get_all_digits();
+
+    learn(&digits);
+
+    let nural_network = NuralNetwork::load_file("./data/rnn_digits.tnn").unwrap();
+    print_predictions(&nural_network, &digits);
+}
+
+fn learn(digits: &[Vec<Vec<u8>>; 10]) {
+    let mut nural_network = NuralNetwork::new(
+        vec![
+            Box::new(ReshapeLayer::new(vec![DIGIT_SIZE, DIGIT_SIZE])),
+            Box::new(LstmLayer::new(DIGIT_SIZE, 64).with_truncation(14)),
+            Box::new(DenseLayer::new(64, 10)),
+            Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+        ],
+        0.05,
+        NuralNetworkLossKind::Mse,
+    );
+
+    let data = digit_samples(digits, 0..100)
+        .into_iter()
+        .shuffle()
+        .collect::<Vec<_>>();
+
+    nural_network.train(data.as_slice(), 30);
+    nural_network.save_file("./data/rnn_digits.tnn").unwrap();
+}
@@ -0,0 +1,42 @@
+﻿use crate::digits_network::{digit_samples, get_all_digits, print_predictions, DIGIT_SIZE};
+use crate::nural::activation_layer::{ActivationLayer, ActivationLayerKind};
+use crate::nural::dense_layer::DenseLayer;
+use crate::nural::flatten_layer::FlattenLayer;
+use crate::nural::nural_network::{NuralNetwork, NuralNetworkLossKind};
+use crate::nural::positional_encoding_layer::PositionalEncodingLayer;
+use crate::nural::reshape_layer::ReshapeLayer;
+use crate::nural::transformer_encoder_layer::TransformerEncoderLayer;
+use crate::utils::shuffle_iter::ShuffleIterExt;
+
+// Treats every row of a digit as one token of a 28 step sequence.
+pub fn transformer_digit_network() {
+    let digits = get_all_digits();
+
+    learn(&digits);
+
+    let nural_network = NuralNetwork::load_file("./data/transformer_digits.tnn").unwrap();
+    print_predictions(&nural_network, &digits);
+}
+
+fn learn(digits: &[Vec<Vec<u8>>; 10]) {
+    let mut nural_network = NuralNetwork::new(
+        vec![
+            Box::new(ReshapeLayer::new(vec![DIGIT_SIZE, DIGIT_SIZE])),
+            Box::new(PositionalEncodingLayer::new(DIGIT_SIZE)),
+            Box::new(TransformerEncoderLayer::new(DIGIT_SIZE, 4, 64)),
+            Box::new(FlattenLayer::new()),
+            Box::new(DenseLayer::new(DIGIT_SIZE * DIGIT_SIZE, 10)),
+            Box::new(ActivationLayer::new(ActivationLayerKind::Tanh)),
+        ],
+        0.01,
+        NuralNetworkLossKind::Mse,
+    );
+
+    let data = digit_samples(digits, 0..100)
+        .into_iter()
+        .shuffle()
+        .collect::<Vec<_>>();
+
+    nural_network.train(data.as_slice(), 30);
+    nural_network.save_file("./data/transformer_digits.tnn").unwrap();
+}